@@ -0,0 +1,78 @@
+//-- site configuration ----------------------------------------------------
+// settings from `webgenr.yaml` (or the file given with `--config`)
+
+use crate::error::{Error, IoResultExt, Result};
+use crate::schema::Schema;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "webgenr.yaml";
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // site metadata for templates (`site.title`, `site.author`), also used
+    // for the book
+    pub title: Option<String>,
+    pub author: Option<String>,
+    // gitignore-style patterns for files that aren't part of the site,
+    // added to the ones in `.webgenrignore`
+    pub exclude: Vec<String>,
+    // where pages are written: `plain` (default, like the source path),
+    // `pretty` or a pattern like `/:year/:month/:slug/`
+    pub permalink: Option<String>,
+    // where the site is published, like `https://example.com/blog/`, for
+    // absolute urls in templates
+    pub base_url: Option<String>,
+    // front matter rules for some or all documents
+    pub schemas: Vec<Schema>,
+    // data files for templates, relative to the source directory (`_data`
+    // if not set)
+    pub data_dir: Option<PathBuf>,
+    // generate an index page for directories without one, with the
+    // `section` template
+    pub section_pages: bool,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).with_path(path)?;
+        Self::from_yaml(&text).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn from_yaml(text: &str) -> std::result::Result<Self, serde_yaml::Error> {
+        if text.trim().is_empty() {
+            return Ok(Config::default());
+        }
+        serde_yaml::from_str(text)
+    }
+
+    // `webgenr.yaml` in the current directory, default settings if
+    // there isn't one
+    pub fn load_default() -> Result<Self> {
+        if Path::new(CONFIG_FILE_NAME).exists() {
+            Self::load(CONFIG_FILE_NAME)
+        } else {
+            Ok(Config::default())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_from_yaml() {
+        let config = Config::from_yaml("exclude:\n  - '*.psd'\n  - notes/\n").unwrap();
+        assert_eq!(config.exclude, vec!["*.psd", "notes/"]);
+        assert_eq!(Config::from_yaml("").unwrap(), Config::default());
+        assert!(Config::from_yaml("excludes: [x]").is_err());
+        let config = Config::from_yaml("permalink: pretty").unwrap();
+        assert_eq!(config.permalink.as_deref(), Some("pretty"));
+        let config = Config::from_yaml("title: Notes\nauthor: Ada").unwrap();
+        assert_eq!(config.title.as_deref(), Some("Notes"));
+        assert_eq!(config.author.as_deref(), Some("Ada"));
+    }
+}
@@ -0,0 +1,149 @@
+//-- data files ------------------------------------------------------------
+// yaml, json, toml and csv files in the data directory (`_data` in the
+// source directory) are the `data` template variable, named after the
+// file: `_data/team.yaml` is `data.team`, `_data/releases/v1.csv` is
+// `data.releases.v1`
+
+use crate::document::toml_to_json;
+use crate::error::{Error, IoResultExt, Result};
+use crate::manifest;
+use crate::util::*;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+pub const DEFAULT_DATA_DIR: &str = "_data";
+
+// everything in `dir` and a hash of it, `Null` if there is no such directory
+pub fn load<P: AsRef<Path>>(dir: P) -> Result<(Value, String)> {
+    let dir = dir.as_ref();
+    if !dir.is_dir() {
+        return Ok((Value::Null, String::new()));
+    }
+    let mut data = Map::new();
+    let mut contents = String::new();
+    let walker = WalkDir::new(dir)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter();
+    for entry_result in walker.filter_entry(|e| !e.is_hidden()) {
+        let entry = entry_result?;
+        let path = entry.path();
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !["yaml", "yml", "json", "toml", "csv"].contains(&extension.as_str()) {
+            info!("not a data file: {}", path.display());
+            continue;
+        }
+        let text = fs::read_to_string(path).with_path(path)?;
+        let value = parse(&text, &extension).map_err(|message| Error::Data {
+            path: path.to_path_buf(),
+            message,
+        })?;
+        contents.push_str(&format!("{}\n{}\n", path.display(), text));
+        let rel_path = path
+            .strip_prefix(dir)
+            .expect("strip prefix match")
+            .with_extension("");
+        insert(&mut data, &rel_path, value).map_err(|message| Error::Data {
+            path: path.to_path_buf(),
+            message,
+        })?;
+    }
+    Ok((
+        Value::Object(data),
+        manifest::hash_bytes(contents.as_bytes()),
+    ))
+}
+
+fn parse(text: &str, extension: &str) -> std::result::Result<Value, String> {
+    match extension {
+        "json" => serde_json::from_str(text).map_err(|e| e.to_string()),
+        "toml" => toml::from_str(text)
+            .map(toml_to_json)
+            .map_err(|e| e.message().trim().replace('\n', ", ")),
+        "csv" => parse_csv(text),
+        _ => serde_yaml::from_str(text).map_err(|e| e.to_string()),
+    }
+}
+
+// a list of rows, each an object keyed by the column names in the first row
+fn parse_csv(text: &str) -> std::result::Result<Value, String> {
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let mut rows = Vec::new();
+    for row in reader.deserialize::<BTreeMap<String, String>>() {
+        let row = row.map_err(|e| e.to_string())?;
+        rows.push(Value::Object(
+            row.into_iter()
+                .map(|(name, value)| (name, value.into()))
+                .collect(),
+        ));
+    }
+    Ok(Value::Array(rows))
+}
+
+// `value` at the object path given by `rel_path`, `releases/v1` is
+// `data.releases.v1`
+fn insert(
+    data: &mut Map<String, Value>,
+    rel_path: &Path,
+    value: Value,
+) -> std::result::Result<(), String> {
+    let names: Vec<String> = rel_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let (name, dirs) = names.split_last().expect("file has a name");
+    let mut object = data;
+    for dir in dirs {
+        object = match object
+            .entry(dir.clone())
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(object) => object,
+            _ => return Err(format!("data.{} is also a file", dir)),
+        };
+    }
+    if object.contains_key(name) {
+        return Err(format!(
+            "data.{} is defined more than once",
+            names.join(".")
+        ));
+    }
+    object.insert(name.clone(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_data_values() {
+        assert_eq!(
+            parse("name,role\nAda,lead\n\"Lin, B\",dev\n", "csv").unwrap(),
+            json!([{"name": "Ada", "role": "lead"}, {"name": "Lin, B", "role": "dev"}])
+        );
+        assert_eq!(parse("a = [1, 2]", "toml").unwrap(), json!({"a": [1, 2]}));
+        assert!(parse("a: [1", "yaml").is_err());
+
+        let mut data = Map::new();
+        insert(&mut data, Path::new("team"), json!(["Ada"])).unwrap();
+        insert(&mut data, Path::new("releases/v1"), json!({"date": "2023"})).unwrap();
+        insert(&mut data, Path::new("releases/v2"), json!({})).unwrap();
+        assert_eq!(
+            Value::Object(data.clone()),
+            json!({"team": ["Ada"], "releases": {"v1": {"date": "2023"}, "v2": {}}})
+        );
+        assert!(insert(&mut data, Path::new("team"), json!(1)).is_err());
+        assert!(insert(&mut data, Path::new("team/x"), json!(1)).is_err());
+    }
+}
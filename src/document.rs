@@ -1,61 +1,417 @@
+use crate::error::{Error, IoResultExt, Result};
 use crate::util::*;
+use crate::web::PageVars;
 use crate::Web;
-use anyhow::bail;
-use pulldown_cmark::{Event, Parser as MarkdownParser, Tag};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone};
+use pulldown_cmark::{CowStr, Event, Parser as MarkdownParser, Tag};
 use serde_json;
 use serde_yaml;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
+// output targets, used to include or exclude content per target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Web,
+    Epub,
+}
+
+impl Format {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Web => "web",
+            Format::Epub => "epub",
+        }
+    }
+}
+
+impl std::str::FromStr for Format {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "web" => Ok(Format::Web),
+            "epub" => Ok(Format::Epub),
+            other => Err(Error::Config(format!(
+                "unknown format '{}', expected 'web' or 'epub'",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct FrontMatter {
-    vars: std::collections::HashMap<String, String>,
+    // every variable except `formats`, passed to templates as they are
+    vars: serde_json::Map<String, serde_json::Value>,
+    // `formats: [web]` limits which outputs include the document
+    formats: Option<Vec<Format>>,
+    // lines in the source file up to the end of the front matter
+    lines: usize,
+    // where each variable is, by line in the source file
+    key_lines: BTreeMap<String, usize>,
+}
+
+// where in the front matter text something is wrong, counting from 1
+struct ParseError {
+    line: usize,
+    column: Option<usize>,
+    message: String,
+}
+
+impl ParseError {
+    fn at_line<S: Into<String>>(line: usize, message: S) -> Self {
+        ParseError {
+            line,
+            column: None,
+            message: message.into(),
+        }
+    }
+}
+
+type ParseResult<T> = std::result::Result<T, ParseError>;
+
+// serde_yaml and serde_json add the location to their messages
+fn without_location(message: String) -> String {
+    match message.find(" at line ") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
 }
 
 impl FrontMatter {
-    // currently only supports string data types, like
+    // any yaml values, like
     // ---
     // title: "My Website"
+    // tags: [rust, web]
     // ---
-    // input: if it has yaml front matter, the yaml is removeed
-    // returns: yaml front matter as struct or None
-    pub fn split_yml_from_string<'a>(input: &mut String) -> anyhow::Result<Option<FrontMatter>> {
-        let mut yaml: bool = false;
-        let mut yaml_separator: &str = "";
-        let mut yaml_text: Option<String> = None;
-        for sep in vec!["---\n", "---\r\n"] {
-            if input.starts_with(sep) {
-                yaml = true;
-                yaml_separator = sep;
+    // or the same in toml between `+++` lines, or a json object
+    // input: if it has front matter, the front matter is removeed
+    // returns: front matter as struct or None
+    pub fn split_yml_from_string<'a>(input: &mut String) -> Result<Option<FrontMatter>> {
+        let (front_matter, body_offset) = Self::read(&mut input.as_bytes(), Path::new(""))?;
+        input.drain(..body_offset as usize);
+        Ok(front_matter)
+    }
+
+    // variables from a directory's `_defaults.yaml`, `path` is only used
+    // for error messages
+    pub fn parse_defaults(text: &str, path: &Path) -> Result<FrontMatter> {
+        Self::parse_yaml(text).map_err(|e| Error::FrontMatter {
+            path: path.to_path_buf(),
+            line: e.line,
+            column: e.column,
+            message: e.message,
+        })
+    }
+
+    // `self` with `defaults` for the variables it doesn't set
+    pub fn with_defaults(mut self, defaults: &FrontMatter) -> FrontMatter {
+        for (key, value) in defaults.vars.iter() {
+            if !self.vars.contains_key(key) {
+                self.vars.insert(key.clone(), value.clone());
             }
         }
+        if self.formats.is_none() {
+            self.formats = defaults.formats.clone();
+        }
+        self
+    }
 
-        if yaml {
-            let mut split = input.split(yaml_separator);
-            let _empty_string = split.next(); // split will give us empty string after the newline
-            if let Some(yaml_str) = split.next() {
-                yaml_text = Some(yaml_str.to_string());
+    fn is_empty(&self) -> bool {
+        self.vars.is_empty() && self.formats.is_none()
+    }
+
+    // reads only the front matter lines from the start of `reader`, `path`
+    // is only used for error messages
+    // returns: front matter (if any) and the byte offset where the body starts
+    pub fn read<R: BufRead>(reader: &mut R, path: &Path) -> Result<(Option<FrontMatter>, u64)> {
+        let mut line = String::new();
+        let mut offset = reader.read_line(&mut line).with_path(path)?;
+        // a byte order mark isn't part of the front matter or the body
+        let bom = if line.starts_with('\u{feff}') {
+            line.drain(..'\u{feff}'.len_utf8());
+            '\u{feff}'.len_utf8()
+        } else {
+            0
+        };
+        let syntax = match Syntax::from_first_line(&line) {
+            Some(syntax) => syntax,
+            None => return Ok((None, bom as u64)),
+        };
+        let mut lines = 1;
+        // json front matter starts on the first line, the others after it
+        let mut text = match syntax {
+            Syntax::Json => line.clone(),
+            _ => String::new(),
+        };
+        while !(syntax == Syntax::Json && Self::is_json_end(&text, &line)) {
+            line.clear();
+            let count = reader.read_line(&mut line).with_path(path)?;
+            if count == 0 {
+                // no closing separator, so this isn't front matter
+                return Ok((None, bom as u64));
+            }
+            offset += count;
+            lines += 1;
+            if syntax.is_separator(&line) {
+                break;
+            }
+            text.push_str(&line);
+        }
+        let (first_line, parsed) = match syntax {
+            Syntax::Yaml => (2, Self::parse_yaml(&text)),
+            Syntax::Toml => (2, Self::parse_toml(&text)),
+            Syntax::Json => (1, Self::parse_json(&text)),
+        };
+        let mut front_matter = parsed.map_err(|e| Error::FrontMatter {
+            path: path.to_path_buf(),
+            line: e.line + first_line - 1,
+            column: e.column,
+            message: e.message,
+        })?;
+        front_matter.lines = lines;
+        for key_line in front_matter.key_lines.values_mut() {
+            *key_line += first_line - 1;
+        }
+        Ok((Some(front_matter), offset as u64))
+    }
+
+    // the json object is complete when its closing brace is on its own
+    // line, or ends a line and the object parses
+    fn is_json_end(text: &str, line: &str) -> bool {
+        let line = line.trim_end();
+        line == "}"
+            || (line.ends_with('}') && serde_json::from_str::<serde::de::IgnoredAny>(text).is_ok())
+    }
+
+    // errors have the line (within `yaml_string`) and a message
+    fn parse_yaml(yaml_string: &str) -> ParseResult<FrontMatter> {
+        let value: serde_yaml::Value = serde_yaml::from_str(yaml_string).map_err(|e| {
+            let location = e.location();
+            ParseError {
+                line: location.as_ref().map_or(1, |l| l.line()),
+                column: location.as_ref().map(|l| l.column()),
+                message: without_location(e.to_string()),
             }
-            if let Some(yaml_string) = yaml_text {
-                input.drain(..yaml_string.len() + yaml_separator.len() * 2);
-                // println!("========== yaml ===========");
-                // println!("{}", &yaml_string);
-                // println!("========== text ===========");
-                // println!("{}", &input);
-                // println!("===========================");
-                return Ok(Some(FrontMatter {
-                    vars: serde_yaml::from_str(&yaml_string)?, // TODO: support more complex data types?
-                }));
+        })?;
+        let mapping = match value {
+            serde_yaml::Value::Null => Default::default(),
+            serde_yaml::Value::Mapping(mapping) => mapping,
+            _ => return Err(ParseError::at_line(1, "expected `name: value` pairs")),
+        };
+        let mut vars = serde_json::Map::new();
+        for (key, value) in mapping {
+            let key: String = serde_yaml::from_value(key)
+                .map_err(|e| ParseError::at_line(1, format!("invalid variable name: {}", e)))?;
+            let value = serde_json::to_value(value).map_err(|e| {
+                ParseError::at_line(Self::key_line(yaml_string, &key), format!("{}: {}", key, e))
+            })?;
+            vars.insert(key, value);
+        }
+        Self::from_vars(vars, yaml_string)
+    }
+
+    // `+++` front matter, like hugo and zola
+    fn parse_toml(toml_string: &str) -> ParseResult<FrontMatter> {
+        let table: toml::Table = toml::from_str(toml_string).map_err(|e| {
+            let start = e.span().map_or(0, |span| span.start);
+            let before = &toml_string[..start];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            ParseError {
+                line: before.matches('\n').count() + 1,
+                column: Some(before[line_start..].chars().count() + 1),
+                message: e.message().trim().replace('\n', ", "),
             }
+        })?;
+        let vars = table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect();
+        Self::from_vars(vars, toml_string)
+    }
+
+    fn parse_json(json_string: &str) -> ParseResult<FrontMatter> {
+        let value: serde_json::Value =
+            serde_json::from_str(json_string).map_err(|e| ParseError {
+                line: e.line(),
+                // serde_json counts from 0 at the start of a line
+                column: Some(e.column().max(1)),
+                message: without_location(e.to_string()),
+            })?;
+        match value {
+            serde_json::Value::Object(vars) => Self::from_vars(vars, json_string),
+            _ => Err(ParseError::at_line(1, "expected an object")),
         }
-        Ok(None)
+    }
+
+    // front matter from parsed variables, `text` is the source they were
+    // parsed from, for error lines
+    fn from_vars(
+        mut vars: serde_json::Map<String, serde_json::Value>,
+        text: &str,
+    ) -> ParseResult<FrontMatter> {
+        let formats = match vars.remove("formats") {
+            Some(value) => Some(
+                Self::parse_formats(value)
+                    .map_err(|e| ParseError::at_line(Self::key_line(text, "formats"), e))?,
+            ),
+            None => None,
+        };
+        let mut key_lines = BTreeMap::new();
+        for (key, value) in vars.iter_mut() {
+            // `title: 1984` or `slug: 2023` is text, not a number
+            if value.is_number()
+                && matches!(key.as_str(), "title" | "template" | "slug" | "permalink")
+            {
+                *value = value.to_string().into();
+            }
+            let line = Self::key_line(text, key);
+            Self::check_var(key, value).map_err(|e| ParseError::at_line(line, e))?;
+            key_lines.insert(key.clone(), line);
+        }
+        Ok(FrontMatter {
+            vars,
+            formats,
+            lines: 0,
+            key_lines,
+        })
+    }
+
+    // type of the variables webgenr uses itself
+    fn check_var(key: &str, value: &serde_json::Value) -> std::result::Result<(), String> {
+        let expected = |what: &str| Err(format!("{}: expected {}, not {}", key, what, value));
+        let is_string_list = |value: &serde_json::Value| {
+            value
+                .as_array()
+                .is_some_and(|items| items.iter().all(|item| item.is_string()))
+        };
+        match (key, value.as_str()) {
+            ("title" | "template" | "slug" | "permalink", None) => expected("a string"),
+            ("draft", _) if !value.is_boolean() => expected("true or false"),
+            ("order" | "weight", _) if !value.is_i64() => expected("a whole number"),
+            ("tags", None) if !is_string_list(value) => expected("a tag or a list of tags"),
+            // `date` is often free text (`April 2023`), it's only used when
+            // it is a date
+            ("publish_date", date) if date.and_then(parse_date).is_none() => {
+                expected("a date like 2023-04-01 or 2023-04-01T09:00:00Z")
+            }
+            ("slug", Some(slug)) if slug.is_empty() || slug.contains('/') || slug == ".." => {
+                expected("a file name")
+            }
+            ("permalink", Some(url)) if url.split('/').any(|segment| segment == "..") => {
+                Err(format!("permalink: '..' isn't allowed in {}", value))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // line of a top-level `key:` (yaml), `key =` (toml) or `"key":` (json)
+    // in `text`, counting from 1
+    fn key_line(text: &str, key: &str) -> usize {
+        let quoted = format!("\"{}\"", key);
+        text.lines()
+            .position(|line| {
+                line.strip_prefix(key)
+                    .or_else(|| line.trim_start().strip_prefix(&quoted))
+                    .is_some_and(|rest| rest.trim_start().starts_with([':', '=']))
+            })
+            .map_or(1, |index| index + 1)
+    }
+
+    // `formats: web` or `formats: [web, epub]`
+    fn parse_formats(value: serde_json::Value) -> std::result::Result<Vec<Format>, String> {
+        let names: Vec<String> = match value {
+            serde_json::Value::Array(_) => serde_json::from_value(value),
+            _ => serde_json::from_value(value).map(|name| vec![name]),
+        }
+        .map_err(|e| format!("formats: {}", e))?;
+        names
+            .iter()
+            .map(|name| name.parse().map_err(|e: Error| e.to_string()))
+            .collect()
+    }
+}
+
+// how front matter is written, from its opening line
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    Yaml, // between `---` lines
+    Toml, // between `+++` lines
+    Json, // an object, starting with `{` on the first line
+}
+
+impl Syntax {
+    fn from_first_line(line: &str) -> Option<Self> {
+        match line.trim_end() {
+            "---" => Some(Syntax::Yaml),
+            "+++" => Some(Syntax::Toml),
+            // `{{` is more likely a template tag in the body
+            line if line.starts_with('{') && !line.starts_with("{{") => Some(Syntax::Json),
+            _ => None,
+        }
+    }
+
+    // line after the front matter, json ends with its closing brace instead
+    fn is_separator(self, line: &str) -> bool {
+        let line = line.trim_end();
+        match self {
+            // yaml documents can also end with `...`
+            Syntax::Yaml => line == "---" || line == "...",
+            Syntax::Toml => line == "+++",
+            Syntax::Json => false,
+        }
+    }
+}
+
+// toml dates become strings, like they are in yaml front matter
+pub(crate) fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    match value {
+        toml::Value::String(s) => s.into(),
+        toml::Value::Integer(i) => i.into(),
+        toml::Value::Float(f) => f.into(),
+        toml::Value::Boolean(b) => b.into(),
+        toml::Value::Datetime(date) => date.to_string().into(),
+        toml::Value::Array(items) => items.into_iter().map(toml_to_json).collect(),
+        toml::Value::Table(table) => table
+            .into_iter()
+            .map(|(key, value)| (key, toml_to_json(value)))
+            .collect(),
     }
 }
 
+// `2023-04-01` (midnight, local time), an RFC 3339 date and time, one
+// without an offset like toml's `2023-04-01T09:00:00` (local time), or
+// jekyll's `2023-04-01 09:00:00 -0500`
+pub(crate) fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    let value = value.trim();
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Some(date_time);
+    }
+    if let Ok(date_time) = DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f %z") {
+        return Some(date_time);
+    }
+    let local = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })?;
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .map(|date_time| date_time.fixed_offset())
+}
+
 pub enum DocumentInfo {
     Markdown {
         front_matter: Option<FrontMatter>,
-        text: String,
+        // the body is read from the source file when it's rendered
+        body_offset: u64,
     },
     Other,
 }
@@ -65,16 +421,15 @@ pub struct Document {
 }
 
 impl Document {
-    pub fn new<P: AsRef<Path>>(source_path: P) -> anyhow::Result<Self> {
+    pub fn new<P: AsRef<Path>>(source_path: P) -> Result<Self> {
         let source_pathbuf = source_path.as_ref().to_path_buf();
         let info = if source_pathbuf.is_markdown() {
-            let mut f = fs::File::open(&source_path)?;
-            let mut markdown = String::new();
-            f.read_to_string(&mut markdown)?;
-            let front_matter = FrontMatter::split_yml_from_string(&mut markdown)?;
+            let file = fs::File::open(&source_path).with_path(&source_path)?;
+            let (front_matter, body_offset) =
+                FrontMatter::read(&mut BufReader::new(file), &source_pathbuf)?;
             DocumentInfo::Markdown {
                 front_matter,
-                text: markdown,
+                body_offset,
             }
         } else {
             DocumentInfo::Other
@@ -84,64 +439,260 @@ impl Document {
             info,
         })
     }
+    // front matter variables the document doesn't set come from `defaults`
+    pub fn apply_defaults(&mut self, defaults: &FrontMatter) {
+        if defaults.is_empty() {
+            return;
+        }
+        if let DocumentInfo::Markdown { front_matter, .. } = &mut self.info {
+            let own = front_matter.take().unwrap_or_default();
+            *front_matter = Some(own.with_defaults(defaults));
+        }
+    }
+
     // The only reason this would fail is if at some point we create a
     // Document with path from user input.
     // Right now that would only happen for developer error, so this
     // is simply wrapper function for rust std api providing nice error messages
-    pub fn file_stem(&self) -> anyhow::Result<&str> {
+    pub fn file_stem(&self) -> Result<&str> {
+        let invalid = |message: &str| {
+            Error::io(
+                &self.source_path,
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, message),
+            )
+        };
         match self.source_path.file_stem() {
             Some(os_stem) => match os_stem.to_str() {
                 Some(stem) => Ok(stem),
-                None => bail!(
-                    "Document: could not parse path, perhaps UTF8 conversion error for {}",
-                    self.source_path.display()
-                ),
+                None => Err(invalid(
+                    "Document: could not parse path, perhaps UTF8 conversion error",
+                )),
             },
-            None => bail!(
-                "Document: unexpected empty file name for: {}",
-                self.source_path.display()
-            ),
+            None => Err(invalid("Document: unexpected empty file name")),
+        }
+    }
+
+    // line in the source file where the markdown body starts
+    pub fn body_line(&self) -> usize {
+        match &self.info {
+            DocumentInfo::Markdown {
+                front_matter: Some(front_matter),
+                ..
+            } => front_matter.lines + 1,
+            _ => 1,
+        }
+    }
+
+    // all front matter variables, except `formats`
+    pub fn front_matter(&self) -> Option<&serde_json::Map<String, serde_json::Value>> {
+        match &self.info {
+            DocumentInfo::Markdown {
+                front_matter: Some(front_matter),
+                ..
+            } => Some(&front_matter.vars),
+            _ => None,
         }
     }
 
-    fn outpath(&self, root: &PathBuf, out_dir: &PathBuf) -> std::io::Result<PathBuf> {
-        let rel_path = self
-            .source_path
-            .strip_prefix(&root)
-            .expect("strip prefix match");
-        Ok(out_dir.join(rel_path))
+    // front matter variable, if there is one with the given name
+    pub fn value(&self, name: &str) -> Option<&serde_json::Value> {
+        self.front_matter().and_then(|vars| vars.get(name))
     }
 
-    pub fn gen_html(&self, context: &Web) -> anyhow::Result<String> {
+    // line of a front matter variable in the source file, or the first
+    // line if there is no such variable
+    pub(crate) fn var_line(&self, name: &str) -> usize {
         match &self.info {
-            DocumentInfo::Markdown { front_matter, text } => {
-                // generate html
-                let mut html = Vec::new();
-                Self::write_html(&mut html, &text)?;
-                let html_string = String::from_utf8(html)?;
-
-                // insert into handlebars template
-                let mut template_vars = match front_matter {
-                    Some(front_matter) => front_matter.vars.clone(),
-                    None => Default::default(),
-                };
-                if let Some(_) = template_vars.insert("body".into(), html_string) {
-                    println!("warning: yaml var 'body' will be ignored");
-                }
+            DocumentInfo::Markdown {
+                front_matter: Some(front_matter),
+                ..
+            } => front_matter.key_lines.get(name).copied().unwrap_or(1),
+            _ => 1,
+        }
+    }
 
-                Ok(context
-                    .template_registry
-                    .render("default", &serde_json::json!(template_vars))?)
+    // front matter string variable
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.value(name).and_then(|value| value.as_str())
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.var("title")
+    }
+
+    // front matter `date`, or `publish_date` if there is none
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        self.var("date")
+            .and_then(parse_date)
+            .or_else(|| self.publish_date())
+    }
+
+    // front matter `tags: [a, b]` or `tags: a`
+    pub fn tags(&self) -> Vec<&str> {
+        match self.value("tags") {
+            Some(serde_json::Value::String(tag)) => vec![tag.as_str()],
+            Some(serde_json::Value::Array(tags)) => {
+                tags.iter().filter_map(|tag| tag.as_str()).collect()
             }
-            _ => {
-                // unimplemented, TODO: return appropriate error
-                bail!("unimplemented!")
+            _ => Vec::new(),
+        }
+    }
+
+    // front matter `template`
+    pub fn template(&self) -> Option<&str> {
+        self.var("template")
+    }
+
+    // position in the book, from front matter `order: N`
+    pub fn order(&self) -> Option<i64> {
+        self.value("order").and_then(|value| value.as_i64())
+    }
+
+    // position in the navigation, from front matter `weight: N`
+    pub fn weight(&self) -> Option<i64> {
+        self.value("weight").and_then(|value| value.as_i64())
+    }
+
+    // front matter `draft: true`
+    pub fn is_draft(&self) -> bool {
+        self.value("draft") == Some(&serde_json::Value::Bool(true))
+    }
+
+    // front matter `publish_date`, validated when the document was read
+    pub fn publish_date(&self) -> Option<DateTime<FixedOffset>> {
+        self.var("publish_date").and_then(parse_date)
+    }
+
+    // publish date is later than `now`
+    pub fn is_scheduled(&self, now: DateTime<FixedOffset>) -> bool {
+        self.publish_date().is_some_and(|date| date > now)
+    }
+
+    // why a draft or scheduled document isn't published, unless `drafts`
+    // or `future` documents are included
+    pub fn held_back(&self, drafts: bool, future: bool) -> Option<String> {
+        if self.is_draft() && !drafts {
+            Some("draft".into())
+        } else if !future && self.is_scheduled(Local::now().fixed_offset()) {
+            Some(format!(
+                "scheduled for {}",
+                self.var("publish_date").unwrap_or_default().trim()
+            ))
+        } else {
+            None
+        }
+    }
+
+    // false if front matter `formats` excludes this document from `format`
+    pub fn includes_format(&self, format: Format) -> bool {
+        match &self.info {
+            DocumentInfo::Markdown {
+                front_matter:
+                    Some(FrontMatter {
+                        formats: Some(formats),
+                        ..
+                    }),
+                ..
+            } => formats.contains(&format),
+            _ => true,
+        }
+    }
+
+    // name of the handlebars template used to render this document, front
+    // matter `template` or `default`
+    pub fn template_name(&self) -> Option<&str> {
+        if self.is_markdown() {
+            Some(self.template().unwrap_or("default"))
+        } else {
+            None
+        }
+    }
+
+    fn not_markdown(&self) -> Error {
+        Error::io(
+            &self.source_path,
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a markdown file"),
+        )
+    }
+
+    // markdown text after the front matter, read from the source file
+    fn read_body(&self) -> Result<String> {
+        let body_offset = match &self.info {
+            DocumentInfo::Markdown { body_offset, .. } => *body_offset,
+            DocumentInfo::Other => return Err(self.not_markdown()),
+        };
+        let mut markdown = String::new();
+        fs::File::open(&self.source_path)
+            .and_then(|mut f| {
+                f.seek(SeekFrom::Start(body_offset))?;
+                f.read_to_string(&mut markdown)
+            })
+            .with_path(&self.source_path)?;
+        Ok(markdown)
+    }
+
+    // render markdown document as a complete html page for `format`,
+    // written to `writer` as the template is rendered
+    // returns: url of each source file the page links to, by manifest key
+    pub fn write_page<W: Write>(
+        &self,
+        context: &Web,
+        format: Format,
+        writer: W,
+    ) -> Result<BTreeMap<String, String>> {
+        let front_matter = match &self.info {
+            DocumentInfo::Markdown { front_matter, .. } => front_matter,
+            DocumentInfo::Other => return Err(self.not_markdown()),
+        };
+        // generate html
+        let mut html = Vec::new();
+        let text = Self::filter_format_blocks(&self.read_body()?, format);
+        // links to other files follow them to where they are written
+        let links = RefCell::new(BTreeMap::new());
+        let link_url = |link: &str| context.link_url(self, link, format, &mut links.borrow_mut());
+        Self::write_html(&mut html, &text, &link_url).with_path(&self.source_path)?;
+        // pulldown_cmark only writes utf8 for utf8 input
+        let html = String::from_utf8(html)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned());
+
+        // insert into handlebars template
+        let own = front_matter.as_ref().map(|front_matter| &front_matter.vars);
+        for name in PageVars::NAMES {
+            if own.is_some_and(|vars| vars.contains_key(name)) {
+                context.warn(
+                    &self.source_path,
+                    &format!("yaml var '{}' will be ignored", name),
+                );
             }
         }
+        // where the page is, for links to other pages and assets
+        let page_url = match format {
+            Format::Web => context.url(self),
+            Format::Epub => format!("{}.xhtml", self.file_stem()?),
+        };
+        let mut vars = context.page_vars(&page_url);
+        vars.own = own;
+        vars.body = Some(&html);
+        // drafts and scheduled documents are only generated for previews
+        let banner = self.held_back(false, false);
+        vars.banner = banner.as_deref();
+
+        let template = self.template_name().expect("markdown has a template");
+        context.render_page(template, &vars, writer)?;
+        Ok(links.into_inner())
     }
 
-    pub fn webgen(&self, context: &Web) -> anyhow::Result<()> {
-        let outpath = self.outpath(&context.in_path, &context.out_path)?;
+    fn write_page_file(&self, context: &Web, path: &Path) -> Result<BTreeMap<String, String>> {
+        let file = fs::File::create(path).with_path(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+        let links = self.write_page(context, Format::Web, &mut writer)?;
+        writer.flush().with_path(path)?;
+        Ok(links)
+    }
+
+    // returns: the pages it links to, like `write_page`
+    pub fn webgen(&self, context: &Web) -> Result<BTreeMap<String, String>> {
+        let outpath = context.outpath(self);
         match &self.info {
             DocumentInfo::Other => {
                 // copy file
@@ -150,45 +701,33 @@ impl Document {
                     self.source_path.display(),
                     &outpath.display()
                 );
-                std::fs::copy(&self.source_path, outpath)?;
+                // building into the source directory, nothing to copy
+                if !self.source_path.is_same_file(&outpath) {
+                    std::fs::copy(&self.source_path, &outpath).with_path(&outpath)?;
+                }
             }
-            DocumentInfo::Markdown {
-                front_matter: _,
-                text: _,
-            } => {
-                let out_file = fs::OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .open(outpath.with_extension("html"))?;
+            DocumentInfo::Markdown { .. } => {
                 info!(
                     "convert-> {}\t{}",
                     self.source_path.display(),
-                    outpath.with_extension("html").display()
+                    outpath.display()
                 );
-                let mut writer = std::io::BufWriter::new(out_file);
-
-                // // generate html
-                // let mut html = Vec::new();
-                // Self::write_html(&mut html, &text)?;
-                // let html_string = String::from_utf8(html)?;
-
-                // // insert into handlebars template
-                // let mut template_vars = match front_matter {
-                //     Some(front_matter) => front_matter.vars.clone(),
-                //     None => Default::default(),
-                // };
-                // if let Some(_) = template_vars.insert("body".into(), html_string) {
-                //     println!("warning: yaml var 'body' will be ignored");
-                // }
-
-                // let s = context
-                //     .template_registry
-                //     .render("default", &serde_json::json!(template_vars))?;
-                let s = self.gen_html(context)?;
-                writer.write_all(s.as_bytes())?;
+                // render next to the output and rename it into place, so a
+                // failed render doesn't leave an empty or partial page
+                let file_name = outpath.file_name().unwrap_or_default().to_string_lossy();
+                let temp_path = outpath.with_file_name(format!(".{}.tmp", file_name));
+                let links = match self.write_page_file(context, &temp_path) {
+                    Ok(links) => links,
+                    Err(e) => {
+                        let _ = fs::remove_file(&temp_path);
+                        return Err(e);
+                    }
+                };
+                fs::rename(&temp_path, &outpath).with_path(&outpath)?;
+                return Ok(links);
             }
         }
-        Ok(())
+        Ok(BTreeMap::new())
     }
     pub fn is_markdown(&self) -> bool {
         match self.info {
@@ -197,8 +736,53 @@ impl Document {
         }
     }
 
-    // private utility function
-    fn write_html<W: Write>(out_writer: W, markdown: &String) -> anyhow::Result<()> {
+    // keep only the content meant for `format`, blocks look like:
+    // ::: only-web
+    // listen to the audio below
+    // :::
+    fn filter_format_blocks(markdown: &str, format: Format) -> String {
+        let mut result = String::with_capacity(markdown.len());
+        // nested blocks, Some(true) if included, None for other `:::` divs,
+        // which are kept with their fences
+        let mut blocks: Vec<Option<bool>> = Vec::new();
+        let mut code_fence: Option<&str> = None;
+        for line in markdown.split_inclusive('\n') {
+            let trimmed = line.trim();
+            if let Some(fence) = code_fence {
+                if trimmed.starts_with(fence) {
+                    code_fence = None;
+                }
+            } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                code_fence = Some(&trimmed[..3]);
+            } else if let Some(rest) = trimmed.strip_prefix(":::") {
+                let rest = rest.trim();
+                if rest.is_empty() {
+                    // closes the innermost block
+                    if let Some(Some(_)) = blocks.pop() {
+                        continue;
+                    }
+                } else if let Some(name) = rest.strip_prefix("only-") {
+                    blocks.push(Some(name.trim() == format.name()));
+                    continue;
+                } else {
+                    blocks.push(None);
+                }
+            }
+            if blocks.iter().all(|included| included.unwrap_or(true)) {
+                result.push_str(line);
+            }
+        }
+        result
+    }
+
+    // private utility function, `link_url` gives the url for relative
+    // links and images, links to markdown files it doesn't know are just
+    // changed to `.html`
+    fn write_html<W: Write>(
+        out_writer: W,
+        markdown: &String,
+        link_url: &dyn Fn(&str) -> Option<String>,
+    ) -> std::io::Result<()> {
         // Set up pulldown_cmark options and parser.
         let mut options = pulldown_cmark::Options::empty();
         // Strikethroughs are not part of the CommonMark standard
@@ -211,48 +795,55 @@ impl Document {
         while let Some(event) = parser.next() {
             let next_event = match event {
                 Event::Start(Tag::Link(link_type, url, title)) => {
-                    let url_string = url.to_string();
-                    match url.mimetype() {
-                        None => {
-                            // no extension or no matching mime for extension
-                            // just return the link unmodified
-                            Event::Start(Tag::Link(link_type, url, title))
-                        }
-                        Some(mimetype) => {
-                            match (mimetype.type_(), mimetype.subtype()) {
-                                (mime::TEXT, subtype) if subtype == "markdown" => {
-                                    // already know we have valid URL with extension
-                                    // so no need for additional error checking
-                                    let ext = Path::new(&url_string).get_ext_str().unwrap();
-                                    let new_url: String =
-                                        format!("{}html", url.trim_end_matches(ext));
-                                    Event::Start(Tag::Link(link_type, new_url.into(), title))
-                                }
-                                (mime::AUDIO, _) => {
-                                    info!("mime::AUDIO: {}", mimetype);
-
-                                    let link_text = if let Some(next_event) = parser.next() {
-                                        if let Event::Text(text) = next_event {
-                                            parser.next(); // skip past Event::End
-                                            text
-                                        } else {
-                                            // no text event, just Event::End
-                                            "#".into()
-                                        }
-                                    } else {
-                                        "".into()
-                                    };
-                                    let link_tag= format!("<a href=\"{}\" title=\"{}\" class=\"audio\"><span class=\"fa-solid fa-play\">{}</span></a>",
-                                        &url, &title, &link_text);
-                                    let audio_tag= format!("<audio controls><source src=\"{}\" type=\"{}\">Your browser does not support the audio element. {}</audio>",
-                                        url, mimetype, &link_tag);
-                                    Event::Html(audio_tag.into())
-                                }
-                                (_, _) => Event::Start(Tag::Link(link_type, url, title)),
+                    let (url_path, suffix) = split_url_suffix(&url);
+                    let mimetype = Path::new(url_path).mimetype();
+                    let new_url = link_url(url_path)
+                        .map(|new_url| format!("{}{}", new_url, suffix))
+                        .or_else(|| match &mimetype {
+                            Some(mimetype)
+                                if mimetype.type_() == mime::TEXT
+                                    && mimetype.subtype() == "markdown" =>
+                            {
+                                // already know we have valid URL with extension
+                                // so no need for additional error checking
+                                let ext = Path::new(url_path).get_ext_str().unwrap();
+                                Some(format!("{}html{}", url_path.trim_end_matches(ext), suffix))
                             }
+                            _ => None,
+                        });
+                    let url = new_url.map(CowStr::from).unwrap_or(url);
+                    match mimetype {
+                        Some(mimetype) if mimetype.type_() == mime::AUDIO => {
+                            info!("mime::AUDIO: {}", mimetype);
+
+                            let link_text = if let Some(next_event) = parser.next() {
+                                if let Event::Text(text) = next_event {
+                                    parser.next(); // skip past Event::End
+                                    text
+                                } else {
+                                    // no text event, just Event::End
+                                    "#".into()
+                                }
+                            } else {
+                                "".into()
+                            };
+                            let link_tag= format!("<a href=\"{}\" title=\"{}\" class=\"audio\"><span class=\"fa-solid fa-play\">{}</span></a>",
+                                &url, &title, &link_text);
+                            let audio_tag= format!("<audio controls><source src=\"{}\" type=\"{}\">Your browser does not support the audio element. {}</audio>",
+                                url, mimetype, &link_tag);
+                            Event::Html(audio_tag.into())
                         }
+                        // no extension, or any other kind of file
+                        _ => Event::Start(Tag::Link(link_type, url, title)),
                     }
                 }
+                Event::Start(Tag::Image(link_type, url, title)) => {
+                    let (url_path, suffix) = split_url_suffix(&url);
+                    let new_url =
+                        link_url(url_path).map(|new_url| format!("{}{}", new_url, suffix));
+                    let url = new_url.map(CowStr::from).unwrap_or(url);
+                    Event::Start(Tag::Image(link_type, url, title))
+                }
                 _ => event,
             };
             new_event_list.push(next_event);
@@ -263,6 +854,11 @@ impl Document {
     }
 }
 
+// `#fragment` or `?query` split off a url, to be kept when the url changes
+fn split_url_suffix(url: &str) -> (&str, &str) {
+    url.split_at(url.find(['#', '?']).unwrap_or(url.len()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -276,7 +872,7 @@ mod tests {
     fn test_write_html_empty() {
         let markdown = "".to_string();
         let mut output = Vec::new();
-        Document::write_html(&mut output, &markdown).unwrap();
+        Document::write_html(&mut output, &markdown, &|_| None).unwrap();
         assert_eq!(&output, EMPTY_BUF);
     }
 
@@ -284,7 +880,7 @@ mod tests {
     fn test_write_html_simple_string() {
         let markdown: String = HELLO_MD.to_string();
         let mut output = Vec::new();
-        Document::write_html(&mut output, &markdown).unwrap();
+        Document::write_html(&mut output, &markdown, &|_| None).unwrap();
         let output_str = std::str::from_utf8(&output).unwrap();
         assert_eq!(output_str, HELLO_HTML);
     }
@@ -298,7 +894,7 @@ mod tests {
         test_data.iter().for_each(|test| {
             let markdown: String = test.md.to_string();
             let mut output = Vec::new();
-            Document::write_html(&mut output, &markdown).unwrap();
+            Document::write_html(&mut output, &markdown, &|_| None).unwrap();
             let output_str = std::str::from_utf8(&output).unwrap();
             assert_eq!(output_str, test.html);
         });
@@ -332,6 +928,209 @@ mod tests {
         verify_write_html_with_test_data(test_data);
     }
 
+    #[test]
+    fn test_filter_format_blocks() {
+        let markdown =
+            "intro\n::: only-web\nlisten below\n:::\n::: only-epub\nsee page 2\n:::\nend\n";
+        assert_eq!(
+            Document::filter_format_blocks(markdown, Format::Web),
+            "intro\nlisten below\nend\n"
+        );
+        assert_eq!(
+            Document::filter_format_blocks(markdown, Format::Epub),
+            "intro\nsee page 2\nend\n"
+        );
+        // markers inside code blocks are left alone
+        let code = "```\n::: only-epub\n```\n";
+        assert_eq!(Document::filter_format_blocks(code, Format::Web), code);
+        // other divs inside a block are closed by their own fence
+        let nested = "::: only-web\n::: note\naudio\n:::\nmore\n:::\nend\n";
+        assert_eq!(
+            Document::filter_format_blocks(nested, Format::Web),
+            "::: note\naudio\n:::\nmore\nend\n"
+        );
+        assert_eq!(
+            Document::filter_format_blocks(nested, Format::Epub),
+            "end\n"
+        );
+    }
+
+    #[test]
+    fn test_front_matter_formats() {
+        let mut markdown = "---\ntitle: web only\nformats: [web]\n---\nhello".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.formats, Some(vec![Format::Web]));
+        assert_eq!(front_matter.vars["title"], "web only");
+        assert_eq!(markdown, "hello");
+
+        // other values keep their yaml types
+        let mut markdown = "---\nformats: [epub]\norder: 1\ndraft: true\n---\nhello".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.formats, Some(vec![Format::Epub]));
+        assert_eq!(front_matter.vars["order"], 1);
+        assert_eq!(front_matter.vars["draft"], true);
+
+        let mut markdown = "---\nformats: pdf\n---\nhello".to_string();
+        assert!(FrontMatter::split_yml_from_string(&mut markdown).is_err());
+    }
+
+    #[test]
+    fn test_front_matter_read_offset() {
+        let text = "---\r\ntitle: crlf\r\n---\r\nbody---\n";
+        let (front_matter, offset) =
+            FrontMatter::read(&mut text.as_bytes(), Path::new("test.md")).unwrap();
+        assert_eq!(front_matter.unwrap().vars["title"], "crlf");
+        assert_eq!(&text[offset as usize..], "body---\n");
+
+        // byte order mark, yaml `...` terminator and `---` in the body
+        let text = "\u{feff}---\ntitle: bom\n...\nbody\n---\nmore\n";
+        let (front_matter, offset) =
+            FrontMatter::read(&mut text.as_bytes(), Path::new("test.md")).unwrap();
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.vars["title"], "bom");
+        assert_eq!(front_matter.lines, 3);
+        assert_eq!(&text[offset as usize..], "body\n---\nmore\n");
+        let text = "\u{feff}# no front matter\n";
+        let (_, offset) = FrontMatter::read(&mut text.as_bytes(), Path::new("test.md")).unwrap();
+        assert_eq!(&text[offset as usize..], "# no front matter\n");
+
+        // errors are at the line and column in the source file
+        let text = "---\ntitle: ok\ntags: [a, b\n---\n";
+        let e = FrontMatter::read(&mut text.as_bytes(), Path::new("test.md"))
+            .err()
+            .unwrap();
+        assert!(e.to_string().starts_with("test.md:4:1: "), "{}", e);
+
+        // without a closing separator the whole file is the body
+        let text = "---\ntitle: unclosed\n";
+        let (front_matter, offset) =
+            FrontMatter::read(&mut text.as_bytes(), Path::new("test.md")).unwrap();
+        assert!(front_matter.is_none());
+        assert_eq!(offset, 0);
+    }
+
+    #[test]
+    fn test_front_matter_values() {
+        let mut markdown = "---\ntitle: rich\ndraft: false\norder: 2\ntags: [a, b]\n\
+                            author: {name: Ann, url: 'https://ann.example'}\n---\n"
+            .to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.vars["draft"], false);
+        assert_eq!(front_matter.vars["order"], 2);
+        assert_eq!(front_matter.vars["tags"], serde_json::json!(["a", "b"]));
+        assert_eq!(front_matter.vars["author"]["name"], "Ann");
+
+        let mut markdown = "---\ntitle: 1984\nslug: 2023\n---\n".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.vars["title"], "1984");
+        assert_eq!(front_matter.vars["slug"], "2023");
+
+        for (yaml, line) in [("title: [a]\n", 1), ("x: 1\ntags: {a: 1}\n", 2)] {
+            let mut markdown = format!("---\n{}---\n", yaml);
+            match FrontMatter::split_yml_from_string(&mut markdown) {
+                Err(Error::FrontMatter { line: l, .. }) => assert_eq!(l, line + 1),
+                _ => panic!("expected an error for {}", yaml),
+            }
+        }
+    }
+
+    #[test]
+    fn test_front_matter_defaults() {
+        let path = Path::new("_defaults.yaml");
+        let parent = FrontMatter::parse_defaults("author: Ann\ntags: [a]\n", path).unwrap();
+        let child = FrontMatter::parse_defaults("tags: [b]\nformats: web\n", path).unwrap();
+        let mut markdown = "---\ntitle: page\nauthor: Bo\n---\n".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap()
+            .with_defaults(&child.with_defaults(&parent));
+        assert_eq!(front_matter.vars["author"], "Bo");
+        assert_eq!(front_matter.vars["tags"], serde_json::json!(["b"]));
+        assert_eq!(front_matter.formats, Some(vec![Format::Web]));
+        assert!(FrontMatter::parse_defaults("draft: maybe", path).is_err());
+    }
+
+    #[test]
+    fn test_front_matter_toml_and_json() {
+        let mut markdown =
+            "+++\ntitle = \"toml\"\ndate = 2023-04-01\ntags = [\"a\"]\n+++\nbody".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.vars["date"], "2023-04-01");
+        assert_eq!(front_matter.vars["tags"], serde_json::json!(["a"]));
+        assert_eq!(markdown, "body");
+        // a local date and time, without an offset
+        let mut markdown = "+++\npublish_date = 2023-04-01T09:00:00\n+++\n".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.vars["publish_date"], "2023-04-01T09:00:00");
+
+        let mut markdown =
+            "{\n  \"title\": \"json\",\n  \"formats\": [\"web\"]\n}\nbody".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.vars["title"], "json");
+        assert_eq!(front_matter.formats, Some(vec![Format::Web]));
+        assert_eq!(markdown, "body");
+        let mut markdown = "{\"title\": \"one line\"}\nbody".to_string();
+        assert!(FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .is_some());
+
+        let mut markdown = "+++\ntitle = \"t\"\ndraft = \"no\"\n+++\n".to_string();
+        assert!(matches!(
+            FrontMatter::split_yml_from_string(&mut markdown),
+            Err(Error::FrontMatter { line: 3, .. })
+        ));
+        let mut markdown = "{{ handlebars }}\n".to_string();
+        assert!(FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_front_matter_publish_date() {
+        let date = parse_date("2023-04-01T09:00:00Z").unwrap();
+        assert_eq!(date.to_rfc3339(), "2023-04-01T09:00:00+00:00");
+        assert!(parse_date("2023-04-01").is_some());
+        assert!(parse_date("April 1st").is_none());
+        let local = Local.with_ymd_and_hms(2023, 4, 1, 9, 0, 0).unwrap();
+        assert_eq!(
+            parse_date("2023-04-01T09:00:00"),
+            Some(local.fixed_offset())
+        );
+        assert_eq!(
+            parse_date("2023-04-01 09:00:00"),
+            Some(local.fixed_offset())
+        );
+        let date = parse_date("2023-04-01 09:00:00 -0500").unwrap();
+        assert_eq!(date.to_rfc3339(), "2023-04-01T09:00:00-05:00");
+
+        let mut markdown = "---\ntitle: t\npublish_date: 2023-13-01\n---\n".to_string();
+        assert!(matches!(
+            FrontMatter::split_yml_from_string(&mut markdown),
+            Err(Error::FrontMatter { line: 3, .. })
+        ));
+        let mut markdown = "---\ndraft: maybe\n---\n".to_string();
+        assert!(FrontMatter::split_yml_from_string(&mut markdown).is_err());
+        let mut markdown = "---\ndate: April 2023\n---\n".to_string();
+        let front_matter = FrontMatter::split_yml_from_string(&mut markdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(front_matter.vars["date"], "April 2023");
+    }
+
     #[test]
     // test of converting markdwon links to .html
     fn test_write_html_link_to_markdown() {
@@ -346,7 +1145,24 @@ mod tests {
                 md: "link: [thing](https://example.com/thing.markdown)",
                 html: "<p>link: <a href=\"https://example.com/thing.html\">thing</a></p>\n",
             },
+            TestData {
+                // the fragment is kept
+                md: "link: [thing](thing.md#part-2)",
+                html: "<p>link: <a href=\"thing.html#part-2\">thing</a></p>\n",
+            },
         ];
         verify_write_html_with_test_data(test_data);
+
+        // permalinks of other pages
+        let markdown = "[two](two.md#top)".to_string();
+        let mut output = Vec::new();
+        Document::write_html(&mut output, &markdown, &|link| {
+            Some(format!("../{}/", link.trim_end_matches(".md")))
+        })
+        .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&output).unwrap(),
+            "<p><a href=\"../two/#top\">two</a></p>\n"
+        );
     }
 }
@@ -0,0 +1,187 @@
+//-- errors ----------------------------------------------------------------
+// everything the library can fail with, so tools embedding webgenr can tell
+// a typo in front matter from a missing file
+
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    // invalid front matter in a markdown file, `line` and `column` (if
+    // known) count from 1 in the source file
+    #[error("{}:{line}{}: {message}", path.display(), column_suffix(column))]
+    FrontMatter {
+        path: PathBuf,
+        line: usize,
+        column: Option<usize>,
+        message: String,
+    },
+    // template that doesn't parse or fails to render
+    #[error("template {}: {message}", template_location(name, line))]
+    Template {
+        name: Option<String>,
+        line: Option<usize>,
+        message: String,
+    },
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    // data file that doesn't parse
+    #[error("{}: {message}", path.display())]
+    Data { path: PathBuf, message: String },
+    // creating an epub, or reading one when importing
+    #[error("epub: {0}")]
+    Epub(String),
+    // invalid settings or command line options
+    #[error("{0}")]
+    Config(String),
+    // front matter errors and schema violations in more than one place,
+    // collected from the whole site
+    #[error("{}", error_list(.0))]
+    Validation(Vec<Error>),
+    // failure while generating a single source file
+    #[error("{}: {source}", path.display())]
+    Document {
+        path: PathBuf,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+fn error_list(errors: &[Error]) -> String {
+    let mut list = format!("{} front matter errors:", errors.len());
+    for e in errors {
+        list.push_str(&format!("\n  {}", e));
+    }
+    list
+}
+
+// `:column`, if we know it
+fn column_suffix(column: &Option<usize>) -> String {
+    column
+        .map(|column| format!(":{}", column))
+        .unwrap_or_default()
+}
+
+// `name:line`, as much of it as we know
+fn template_location(name: &Option<String>, line: &Option<usize>) -> String {
+    let name = name.as_deref().unwrap_or("(unknown)");
+    match line {
+        Some(line) => format!("{}:{}", name, line),
+        None => name.to_string(),
+    }
+}
+
+impl Error {
+    pub fn io<P: AsRef<Path>>(path: P, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn document<P: AsRef<Path>>(path: P, source: Error) -> Self {
+        Error::Document {
+            path: path.as_ref().to_path_buf(),
+            source: Box::new(source),
+        }
+    }
+
+    // the underlying error, for errors about a single document
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Document { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+}
+
+impl From<handlebars::TemplateError> for Error {
+    fn from(e: handlebars::TemplateError) -> Self {
+        Error::Template {
+            name: e.template_name.clone(),
+            line: e.line_no,
+            message: e.reason().to_string(),
+        }
+    }
+}
+
+impl From<handlebars::RenderError> for Error {
+    fn from(e: handlebars::RenderError) -> Self {
+        Error::Template {
+            name: e.template_name.clone(),
+            line: e.line_no,
+            message: e.desc.clone(),
+        }
+    }
+}
+
+impl From<walkdir::Error> for Error {
+    fn from(e: walkdir::Error) -> Self {
+        let path = e.path().map(Path::to_path_buf).unwrap_or_default();
+        let source = match e.into_io_error() {
+            Some(source) => source,
+            None => std::io::Error::other("file system loop"),
+        };
+        Error::Io { path, source }
+    }
+}
+
+// adds the path to io errors, like `fs::read(&path).with_path(&path)?`
+pub(crate) trait IoResultExt<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for std::io::Result<T> {
+    fn with_path<P: AsRef<Path>>(self, path: P) -> Result<T> {
+        self.map_err(|e| Error::io(path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_display() {
+        let e = Error::document(
+            "markdown/index.md",
+            Error::Template {
+                name: Some("default".into()),
+                line: Some(3),
+                message: "missing helper".into(),
+            },
+        );
+        assert_eq!(
+            e.to_string(),
+            "markdown/index.md: template default:3: missing helper"
+        );
+        assert!(matches!(e.root(), Error::Template { .. }));
+
+        let e = Error::Validation(vec![
+            Error::FrontMatter {
+                path: "a.md".into(),
+                line: 2,
+                column: Some(7),
+                message: "bad".into(),
+            },
+            Error::FrontMatter {
+                path: "b.md".into(),
+                line: 1,
+                column: None,
+                message: "'title' is required".into(),
+            },
+        ]);
+        assert_eq!(
+            e.to_string(),
+            "2 front matter errors:\n  a.md:2:7: bad\n  b.md:1: 'title' is required"
+        );
+
+        let e = std::fs::read("no/such/file").with_path("no/such/file");
+        assert!(e.unwrap_err().to_string().starts_with("no/such/file: "));
+    }
+}
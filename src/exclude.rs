@@ -0,0 +1,78 @@
+//-- excluded files --------------------------------------------------------
+// gitignore-style rules for files that are never published: hidden files,
+// `_`-prefixed directories, patterns from config and `.webgenrignore`
+
+use crate::error::{Error, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+use walkdir::DirEntry;
+
+pub const IGNORE_FILE_NAME: &str = ".webgenrignore";
+
+// excluded unless a later rule re-includes them, like `!_data/`
+const DEFAULT_RULES: [&str; 2] = [".*", "_*/"];
+
+pub struct Excludes {
+    matcher: Gitignore,
+}
+
+impl Excludes {
+    // rules for the directory tree at `root`, its `.webgenrignore`
+    // (if any) is applied after the default rules and `patterns`
+    pub fn new<P: AsRef<Path>>(root: P, patterns: &[String]) -> Result<Self> {
+        let root = root.as_ref();
+        let invalid = |e: ignore::Error| Error::Config(format!("exclude pattern: {}", e));
+        let mut builder = GitignoreBuilder::new(root);
+        for rule in DEFAULT_RULES {
+            builder.add_line(None, rule).map_err(invalid)?;
+        }
+        for pattern in patterns {
+            builder.add_line(None, pattern).map_err(invalid)?;
+        }
+        let ignore_file = root.join(IGNORE_FILE_NAME);
+        if ignore_file.is_file() {
+            if let Some(e) = builder.add(&ignore_file) {
+                return Err(Error::Config(format!("{}: {}", ignore_file.display(), e)));
+            }
+        }
+        Ok(Excludes {
+            matcher: builder.build().map_err(invalid)?,
+        })
+    }
+
+    // `path` is under the root these rules were created for
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.matcher.matched(path, is_dir).is_ignore()
+    }
+
+    // for `WalkDir::filter_entry`, the root itself is always included
+    pub fn includes(&self, entry: &DirEntry) -> bool {
+        entry.depth() == 0 || !self.is_excluded(entry.path(), entry.file_type().is_dir())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excludes() {
+        let excludes = Excludes::new(
+            "markdown",
+            &[
+                "*.psd".to_string(),
+                "\\#*#".to_string(),
+                "!_data/".to_string(),
+            ],
+        )
+        .unwrap();
+        let excluded = |path: &str, is_dir| excludes.is_excluded(Path::new(path), is_dir);
+        assert!(excluded("markdown/.DS_Store", false));
+        assert!(excluded("markdown/art/cover.psd", false));
+        assert!(excluded("markdown/#notes.md#", false));
+        assert!(excluded("markdown/_drafts", true));
+        assert!(!excluded("markdown/_data", true));
+        assert!(!excluded("markdown/_cover.png", false));
+        assert!(!excluded("markdown/index.md", false));
+    }
+}
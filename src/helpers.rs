@@ -0,0 +1,81 @@
+//-- handlebars helpers ----------------------------------------------------
+// urls in templates, using the `page_url` and `base_url` template variables
+// of the page being rendered:
+//   {{relative_url "style/style.css"}}  like `../style/style.css`
+//   {{absolute_url "feed.xml"}}         like `https://example.com/blog/feed.xml`
+
+use crate::permalink;
+use handlebars::{Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError};
+
+pub fn register(handlebars: &mut Handlebars) {
+    handlebars.register_helper("relative_url", Box::new(relative_url));
+    handlebars.register_helper("absolute_url", Box::new(absolute_url));
+}
+
+// a path relative to the site root, like `style/style.css` or `/notes/`
+fn path_param<'a>(h: &'a Helper, name: &str) -> Result<&'a str, RenderError> {
+    h.param(0)
+        .and_then(|param| param.value().as_str())
+        .map(|path| path.trim_start_matches('/'))
+        .ok_or_else(|| RenderError::new(format!("{}: expected a path", name)))
+}
+
+fn var<'a>(ctx: &'a Context, name: &str) -> &'a str {
+    ctx.data()
+        .get(name)
+        .and_then(|value| value.as_str())
+        .unwrap_or_default()
+}
+
+// relative to the page being rendered, so the site works at any url
+fn relative_url(
+    h: &Helper,
+    _: &Handlebars,
+    ctx: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let path = path_param(h, "relative_url")?;
+    out.write(&permalink::relative_url(var(ctx, "page_url"), path))?;
+    Ok(())
+}
+
+// under `base_url`, or from the domain root without one
+fn absolute_url(
+    h: &Helper,
+    _: &Handlebars,
+    ctx: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let path = path_param(h, "absolute_url")?;
+    let base_url = var(ctx, "base_url").trim_end_matches('/');
+    out.write(&format!("{}/{}", base_url, path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_url_helpers() {
+        let mut handlebars = Handlebars::new();
+        register(&mut handlebars);
+        let template = r#"{{relative_url "style/style.css"}} {{absolute_url "/notes/"}}"#;
+        let page = json!({"page_url": "notes/one/", "base_url": "https://example.com/blog/"});
+        assert_eq!(
+            handlebars.render_template(template, &page).unwrap(),
+            "../../style/style.css https://example.com/blog/notes/"
+        );
+        let page = json!({"page_url": "index.html"});
+        assert_eq!(
+            handlebars.render_template(template, &page).unwrap(),
+            "style/style.css /notes/"
+        );
+        assert!(handlebars
+            .render_template("{{relative_url}}", &page)
+            .is_err());
+    }
+}
@@ -0,0 +1,390 @@
+//-- XHTML to markdown conversion -------------------------------------------
+// epub chapters are well-formed XML, so we walk the tree with roxmltree and
+// emit CommonMark for the elements we know about. Anything we can't express
+// in markdown (tables, svg, math) is passed through as raw html.
+
+use roxmltree::Node;
+
+// html named entities that are commonly found in epub2 content, but
+// are not defined in XML (everything else should be numeric)
+const HTML_ENTITIES: [(&str, &str); 12] = [
+    ("&nbsp;", "&#160;"),
+    ("&ndash;", "&#8211;"),
+    ("&mdash;", "&#8212;"),
+    ("&lsquo;", "&#8216;"),
+    ("&rsquo;", "&#8217;"),
+    ("&ldquo;", "&#8220;"),
+    ("&rdquo;", "&#8221;"),
+    ("&hellip;", "&#8230;"),
+    ("&copy;", "&#169;"),
+    ("&reg;", "&#174;"),
+    ("&trade;", "&#8482;"),
+    ("&shy;", "&#173;"),
+];
+
+// parse XML that may include a DOCTYPE and html named entities
+pub fn parse_xml(text: &str) -> Result<roxmltree::Document<'_>, roxmltree::Error> {
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..Default::default()
+    };
+    roxmltree::Document::parse_with_options(text, options)
+}
+
+pub fn replace_html_entities(text: &str) -> String {
+    let mut result = text.to_string();
+    for (name, number) in HTML_ENTITIES {
+        if result.contains(name) {
+            result = result.replace(name, number);
+        }
+    }
+    result
+}
+
+// returns the text of the `<title>` element or first heading, if any
+pub fn document_title(xhtml: &str) -> Option<String> {
+    let text = replace_html_entities(xhtml);
+    let doc = parse_xml(&text).ok()?;
+    let find = |name: &str| {
+        doc.descendants()
+            .find(|n| n.tag_name().name() == name)
+            .map(|n| collapse_whitespace(&node_text(n)))
+            .filter(|s| !s.is_empty())
+    };
+    find("title").or_else(|| find("h1")).or_else(|| find("h2"))
+}
+
+// convert the `<body>` of an xhtml document to markdown,
+// `rewrite_url` is called for every link and image reference
+pub fn xhtml_to_markdown<F>(xhtml: &str, rewrite_url: F) -> Result<String, roxmltree::Error>
+where
+    F: Fn(&str) -> String,
+{
+    let text = replace_html_entities(xhtml);
+    let doc = parse_xml(&text)?;
+    let root = doc.root_element();
+    let body = root
+        .descendants()
+        .find(|n| n.tag_name().name() == "body")
+        .unwrap_or(root);
+
+    let converter = Converter {
+        source: &text,
+        rewrite_url: &rewrite_url,
+    };
+    let mut blocks = Vec::new();
+    converter.blocks(body, &mut blocks);
+    let mut markdown = blocks.join("\n\n");
+    if !markdown.is_empty() {
+        markdown.push('\n');
+    }
+    Ok(markdown)
+}
+
+struct Converter<'a> {
+    source: &'a str,
+    rewrite_url: &'a dyn Fn(&str) -> String,
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "address"
+            | "article"
+            | "aside"
+            | "blockquote"
+            | "dd"
+            | "div"
+            | "dl"
+            | "dt"
+            | "figcaption"
+            | "figure"
+            | "footer"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "header"
+            | "hr"
+            | "main"
+            | "math"
+            | "nav"
+            | "ol"
+            | "p"
+            | "pre"
+            | "section"
+            | "svg"
+            | "table"
+            | "ul"
+            | "script"
+            | "style"
+    )
+}
+
+fn node_text(node: Node) -> String {
+    node.descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect()
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_ascii_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn escape_markdown_char(c: char, out: &mut String) {
+    match c {
+        '\\' | '*' | '_' | '`' | '[' | ']' => {
+            out.push('\\');
+            out.push(c);
+        }
+        '<' => out.push_str("&lt;"),
+        _ => out.push(c),
+    }
+}
+
+fn escape_markdown(text: &str, out: &mut String) {
+    text.chars().for_each(|c| escape_markdown_char(c, out));
+}
+
+// append text to inline markdown, collapsing whitespace like a browser would
+// (only ascii whitespace, so that non-breaking spaces are kept)
+fn push_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !out.is_empty() && !out.ends_with(' ') && !out.ends_with('\n') {
+                out.push(' ');
+            }
+        } else {
+            escape_markdown_char(c, out);
+        }
+    }
+}
+
+fn flush_inline(inline: &mut String, blocks: &mut Vec<String>) {
+    let text = inline.trim();
+    if !text.is_empty() {
+        blocks.push(text.to_string());
+    }
+    inline.clear();
+}
+
+// prefix every line of `text` (used for blockquotes and list items)
+fn indent(text: &str, first: &str, rest: &str) -> String {
+    let mut result = String::new();
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            result.push('\n');
+        }
+        let prefix = if i == 0 { first } else { rest };
+        if line.is_empty() {
+            result.push_str(prefix.trim_end());
+        } else {
+            result.push_str(prefix);
+            result.push_str(line);
+        }
+    }
+    result
+}
+
+impl Converter<'_> {
+    fn raw(&self, node: Node) -> String {
+        self.source[node.range()].to_string()
+    }
+
+    fn blocks(&self, node: Node, blocks: &mut Vec<String>) {
+        let mut inline = String::new();
+        for child in node.children() {
+            if child.is_element() && is_block(child.tag_name().name()) {
+                flush_inline(&mut inline, blocks);
+                self.block(child, blocks);
+            } else {
+                self.inline(child, &mut inline);
+            }
+        }
+        flush_inline(&mut inline, blocks);
+    }
+
+    fn block(&self, node: Node, blocks: &mut Vec<String>) {
+        let name = node.tag_name().name();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level: usize = name[1..].parse().unwrap_or(1);
+                let text = self.inline_content(node);
+                if !text.is_empty() {
+                    blocks.push(format!("{} {}", "#".repeat(level), text));
+                }
+            }
+            "ul" | "ol" => {
+                let list = self.list(node, name == "ol");
+                if !list.is_empty() {
+                    blocks.push(list);
+                }
+            }
+            "blockquote" => {
+                let mut inner = Vec::new();
+                self.blocks(node, &mut inner);
+                blocks.push(indent(&inner.join("\n\n"), "> ", "> "));
+            }
+            "pre" => {
+                let code = node_text(node);
+                blocks.push(format!("```\n{}\n```", code.trim_end_matches('\n')));
+            }
+            "hr" => blocks.push("* * *".to_string()),
+            "table" | "svg" | "math" => blocks.push(self.raw(node)),
+            "script" | "style" => {}
+            _ => self.blocks(node, blocks),
+        }
+    }
+
+    fn list(&self, node: Node, ordered: bool) -> String {
+        let mut items = Vec::new();
+        for li in node
+            .children()
+            .filter(|n| n.is_element() && n.tag_name().name() == "li")
+        {
+            let mut inner = Vec::new();
+            self.blocks(li, &mut inner);
+            let marker = if ordered {
+                format!("{}. ", items.len() + 1)
+            } else {
+                "* ".to_string()
+            };
+            let continuation = " ".repeat(marker.len());
+            items.push(indent(&inner.join("\n\n"), &marker, &continuation));
+        }
+        items.join("\n")
+    }
+
+    fn inline_content(&self, node: Node) -> String {
+        let mut inline = String::new();
+        for child in node.children() {
+            self.inline(child, &mut inline);
+        }
+        inline.trim().to_string()
+    }
+
+    fn wrap(&self, node: Node, delimiter: &str, out: &mut String) {
+        let content = self.inline_content(node);
+        if !content.is_empty() {
+            out.push_str(delimiter);
+            out.push_str(&content);
+            out.push_str(delimiter);
+        }
+    }
+
+    fn inline(&self, node: Node, out: &mut String) {
+        if node.is_text() {
+            push_text(node.text().unwrap_or_default(), out);
+            return;
+        }
+        if !node.is_element() {
+            return;
+        }
+        match node.tag_name().name() {
+            "em" | "i" | "cite" => self.wrap(node, "*", out),
+            "strong" | "b" => self.wrap(node, "**", out),
+            "code" | "kbd" | "tt" | "samp" => {
+                let code = collapse_whitespace(&node_text(node));
+                if !code.is_empty() {
+                    out.push('`');
+                    out.push_str(&code);
+                    out.push('`');
+                }
+            }
+            "a" => {
+                let content = self.inline_content(node);
+                match node.attribute("href") {
+                    Some(href) => {
+                        out.push('[');
+                        out.push_str(&content);
+                        out.push_str("](");
+                        out.push_str(&(self.rewrite_url)(href));
+                        out.push(')');
+                    }
+                    None => out.push_str(&content),
+                }
+            }
+            "img" => {
+                if let Some(src) = node.attribute("src") {
+                    out.push_str("![");
+                    escape_markdown(node.attribute("alt").unwrap_or_default(), out);
+                    out.push_str("](");
+                    out.push_str(&(self.rewrite_url)(src));
+                    out.push(')');
+                }
+            }
+            "br" => out.push_str("  \n"),
+            "sup" | "sub" | "audio" | "video" => out.push_str(&self.raw(node)),
+            _ => {
+                for child in node.children() {
+                    self.inline(child, out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(body: &str) -> String {
+        let xhtml = format!(
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>T</title></head><body>{}</body></html>",
+            body
+        );
+        xhtml_to_markdown(&xhtml, |url| url.replace(".xhtml", ".md")).unwrap()
+    }
+
+    #[test]
+    fn test_xhtml_to_markdown_basics() {
+        assert_eq!(convert(""), "");
+        assert_eq!(
+            convert("<h1>Title</h1><p>hello\n  world</p>"),
+            "# Title\n\nhello world\n"
+        );
+        assert_eq!(
+            convert("<p>some <em>nice</em> and <strong>bold</strong> text</p>"),
+            "some *nice* and **bold** text\n"
+        );
+        assert_eq!(convert("<p>a * b</p>"), "a \\* b\n");
+    }
+
+    #[test]
+    fn test_xhtml_to_markdown_links_and_images() {
+        assert_eq!(
+            convert("<p>see <a href=\"ch2.xhtml#x\">next</a></p>"),
+            "see [next](ch2.md#x)\n"
+        );
+        assert_eq!(
+            convert("<div><img src=\"../Images/a.png\" alt=\"pic\"/></div>"),
+            "![pic](../Images/a.png)\n"
+        );
+    }
+
+    #[test]
+    fn test_xhtml_to_markdown_lists_and_quotes() {
+        assert_eq!(
+            convert("<ul><li>one</li><li>two</li></ul>"),
+            "* one\n* two\n"
+        );
+        assert_eq!(
+            convert("<ol><li><p>one</p><p>more</p></li><li>two</li></ol>"),
+            "1. one\n\n   more\n2. two\n"
+        );
+        assert_eq!(
+            convert("<blockquote><p>quoted</p></blockquote>"),
+            "> quoted\n"
+        );
+    }
+
+    #[test]
+    fn test_xhtml_entities_and_title() {
+        assert_eq!(convert("<p>a&nbsp;b&mdash;c</p>"), "a\u{a0}b\u{2014}c\n");
+        let xhtml = "<html><head><title> My  Chapter </title></head><body/></html>";
+        assert_eq!(document_title(xhtml), Some("My Chapter".to_string()));
+    }
+}
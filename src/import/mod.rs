@@ -0,0 +1,572 @@
+//-- epub import ----------------------------------------------------------
+// read an existing epub (OPF package, spine and table of contents) and
+// write a markdown source tree that can be rebuilt with `gen_book`
+
+mod markdown;
+
+use crate::error::{Error, IoResultExt, Result};
+use crate::util::*;
+use crate::Web;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+type Archive = zip::ZipArchive<fs::File>;
+
+#[derive(Serialize)]
+struct ChapterFrontMatter<'a> {
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order: Option<usize>,
+}
+
+struct ManifestItem {
+    id: String,
+    path: String, // full path within the zip archive
+    media_type: String,
+    properties: String,
+}
+
+struct Package {
+    title: Option<String>,
+    author: Option<String>,
+    opf_dir: String,
+    items: Vec<ManifestItem>,
+    spine: Vec<String>,
+    toc_id: Option<String>,
+    cover_image_id: Option<String>,
+    guide: Vec<(String, String)>, // (type, path)
+}
+
+impl Package {
+    fn item(&self, id: &str) -> Option<&ManifestItem> {
+        self.items.iter().find(|item| item.id == id)
+    }
+    fn guide_path(&self, reftype: &str) -> Option<&str> {
+        self.guide
+            .iter()
+            .find(|(t, _)| t == reftype)
+            .map(|(_, path)| path.as_str())
+    }
+    // path relative to the directory that contains the OPF file
+    fn rel_path<'a>(&self, path: &'a str) -> &'a str {
+        path.strip_prefix(&self.opf_dir).unwrap_or(path)
+    }
+}
+
+fn read_bytes(archive: &mut Archive, name: &str) -> Result<Vec<u8>> {
+    let mut file = archive
+        .by_name(name)
+        .map_err(|e| Error::Epub(format!("epub is missing {}: {}", name, e)))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)
+        .map_err(|e| Error::Epub(format!("reading {}: {}", name, e)))?;
+    Ok(bytes)
+}
+
+fn read_string(archive: &mut Archive, name: &str) -> Result<String> {
+    String::from_utf8(read_bytes(archive, name)?)
+        .map_err(|e| Error::Epub(format!("reading {}: {}", name, e)))
+}
+
+fn parse_xml<'a>(text: &'a str, name: &str) -> Result<roxmltree::Document<'a>> {
+    markdown::parse_xml(text).map_err(|e| Error::Epub(format!("parsing {}: {}", name, e)))
+}
+
+fn is_external(href: &str) -> bool {
+    href.contains("://") || href.starts_with("mailto:") || href.starts_with("data:")
+}
+
+// resolve `href` relative to the zip entry `base`, returns the
+// normalized zip path and the fragment (including '#'), if any
+fn resolve_href(base: &str, href: &str) -> (String, String) {
+    let (href, fragment) = match href.find('#') {
+        Some(i) => (&href[..i], href[i..].to_string()),
+        None => (href, String::new()),
+    };
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop(); // file name of base
+    let decoded = percent_decode(href);
+    for part in decoded.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            _ => parts.push(part),
+        }
+    }
+    let path = parts
+        .into_iter()
+        .filter(|p| !p.is_empty())
+        .collect::<Vec<_>>()
+        .join("/");
+    (path, fragment)
+}
+
+fn attribute<'a>(node: &roxmltree::Node<'a, '_>, local_name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|attr| attr.name() == local_name)
+        .map(|attr| attr.value())
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    node.descendants()
+        .find(|n| n.tag_name().name() == name)
+        .and_then(|n| n.text())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn read_package(archive: &mut Archive) -> Result<Package> {
+    let container = read_string(archive, "META-INF/container.xml")?;
+    let container_doc = parse_xml(&container, "container.xml")?;
+    let opf_path = match container_doc
+        .descendants()
+        .find(|n| n.tag_name().name() == "rootfile")
+        .and_then(|n| n.attribute("full-path"))
+    {
+        Some(path) => path.to_string(),
+        None => return Err(Error::Epub("container.xml has no rootfile".into())),
+    };
+    info!("epub package: {}", opf_path);
+
+    let opf = read_string(archive, &opf_path)?;
+    let doc = parse_xml(&opf, &opf_path)?;
+    let opf_dir = match opf_path.rfind('/') {
+        Some(i) => opf_path[..=i].to_string(),
+        None => String::new(),
+    };
+
+    let mut package = Package {
+        title: child_text(doc.root_element(), "title"),
+        author: child_text(doc.root_element(), "creator"),
+        opf_dir,
+        items: Vec::new(),
+        spine: Vec::new(),
+        toc_id: None,
+        cover_image_id: None,
+        guide: Vec::new(),
+    };
+    for node in doc.descendants().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "item" => {
+                if let (Some(id), Some(href)) = (node.attribute("id"), node.attribute("href")) {
+                    let properties = node.attribute("properties").unwrap_or_default();
+                    if properties.split_whitespace().any(|p| p == "cover-image") {
+                        package.cover_image_id = Some(id.to_string());
+                    }
+                    package.items.push(ManifestItem {
+                        id: id.to_string(),
+                        path: resolve_href(&opf_path, href).0,
+                        media_type: node.attribute("media-type").unwrap_or_default().to_string(),
+                        properties: properties.to_string(),
+                    });
+                }
+            }
+            "meta"
+                if node.attribute("name") == Some("cover") && package.cover_image_id.is_none() =>
+            {
+                package.cover_image_id = node.attribute("content").map(String::from);
+            }
+            "spine" => package.toc_id = node.attribute("toc").map(String::from),
+            "itemref" if node.attribute("linear") != Some("no") => {
+                if let Some(idref) = node.attribute("idref") {
+                    package.spine.push(idref.to_string());
+                }
+            }
+            "reference" => {
+                if let (Some(reftype), Some(href)) =
+                    (node.attribute("type"), node.attribute("href"))
+                {
+                    let path = resolve_href(&opf_path, href).0;
+                    package.guide.push((reftype.to_string(), path));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(package)
+}
+
+// map of content document path -> title, from the epub3 nav document
+// or the epub2 NCX, whichever is available
+fn read_toc_titles(archive: &mut Archive, package: &Package) -> Result<HashMap<String, String>> {
+    let mut titles = HashMap::new();
+    let nav_item = package
+        .items
+        .iter()
+        .find(|item| item.properties.split_whitespace().any(|p| p == "nav"));
+    let ncx_item = package
+        .toc_id
+        .as_deref()
+        .and_then(|id| package.item(id))
+        .or_else(|| {
+            package
+                .items
+                .iter()
+                .find(|item| item.media_type == "application/x-dtbncx+xml")
+        });
+
+    if let Some(item) = nav_item {
+        let text = markdown::replace_html_entities(&read_string(archive, &item.path)?);
+        let doc = parse_xml(&text, &item.path)?;
+        let toc = doc
+            .descendants()
+            .filter(|n| n.tag_name().name() == "nav")
+            .find(|n| attribute(n, "type") == Some("toc"));
+        if let Some(toc) = toc {
+            for link in toc.descendants().filter(|n| n.tag_name().name() == "a") {
+                if let Some(href) = link.attribute("href") {
+                    let title: String = link
+                        .descendants()
+                        .filter_map(|n| n.text())
+                        .collect::<String>()
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    let path = resolve_href(&item.path, href).0;
+                    titles.entry(path).or_insert(title);
+                }
+            }
+        }
+    } else if let Some(item) = ncx_item {
+        let text = read_string(archive, &item.path)?;
+        let doc = parse_xml(&text, &item.path)?;
+        for nav_point in doc
+            .descendants()
+            .filter(|n| n.tag_name().name() == "navPoint")
+        {
+            let title = child_text(nav_point, "text");
+            let src = nav_point
+                .children()
+                .find(|n| n.tag_name().name() == "content")
+                .and_then(|n| n.attribute("src"));
+            if let (Some(title), Some(src)) = (title, src) {
+                let path = resolve_href(&item.path, src).0;
+                titles.entry(path).or_insert(title);
+            }
+        }
+    }
+    Ok(titles)
+}
+
+fn is_template_resource(media_type: &str) -> bool {
+    media_type == "text/css"
+        || media_type.starts_with("font/")
+        || media_type.starts_with("application/font")
+        || media_type.starts_with("application/x-font")
+        || media_type == "application/vnd.ms-opentype"
+}
+
+fn is_content_document(media_type: &str) -> bool {
+    media_type == "application/xhtml+xml" || media_type == "text/html"
+}
+
+fn write_file<P: AsRef<Path>>(path: P, contents: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    path.create_all_parent_dir().with_path(path)?;
+    fs::write(path, contents).with_path(path)
+}
+
+// convert `epub_path` into markdown files in `source_dir`, stylesheets and
+// fonts go into `template_dir`, returns the number of chapters
+pub fn import_epub<P: AsRef<Path>>(epub_path: P, source_dir: P, template_dir: P) -> Result<usize> {
+    let source_dir = source_dir.as_ref();
+    let template_dir = template_dir.as_ref();
+    if source_dir.exists()
+        && fs::read_dir(source_dir)
+            .with_path(source_dir)?
+            .next()
+            .is_some()
+    {
+        return Err(Error::Config(format!(
+            "refusing to import into non-empty directory: {}",
+            source_dir.display()
+        )));
+    }
+    let file = fs::File::open(&epub_path).with_path(&epub_path)?;
+    let mut archive = zip::ZipArchive::new(file)
+        .map_err(|e| Error::Epub(format!("opening {}: {}", epub_path.as_ref().display(), e)))?;
+    let package = read_package(&mut archive)?;
+    let titles = read_toc_titles(&mut archive, &package)?;
+
+    if !template_dir.exists() {
+        Web::inflate_default_templates(template_dir)?;
+    }
+    fs::create_dir_all(source_dir).with_path(source_dir)?;
+
+    // decide where every file ends up, relative to source root
+    let cover_path = package
+        .cover_image_id
+        .as_deref()
+        .and_then(|id| package.item(id))
+        .map(|item| item.path.as_str());
+    let title_page_path = package.guide_path("title-page");
+    let cover_page_path = package.guide_path("cover");
+    let nav_ids: Vec<&str> = package
+        .items
+        .iter()
+        .filter(|item| {
+            item.properties.split_whitespace().any(|p| p == "nav")
+                || Some(item.id.as_str()) == package.toc_id.as_deref()
+        })
+        .map(|item| item.id.as_str())
+        .collect();
+
+    let mut chapters: Vec<(&ManifestItem, String)> = Vec::new();
+    for idref in &package.spine {
+        let item = match package.item(idref) {
+            Some(item) => item,
+            None => {
+                println!("warning: spine references missing item '{}'", idref);
+                continue;
+            }
+        };
+        if nav_ids.contains(&item.id.as_str()) || Some(item.path.as_str()) == cover_page_path {
+            continue;
+        }
+        let file_name = if Some(item.path.as_str()) == title_page_path {
+            "_title.md".to_string()
+        } else {
+            let stem = Path::new(&item.path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| item.id.clone());
+            format!("{:02}-{}.md", chapters.len() + 1, stem)
+        };
+        chapters.push((item, file_name));
+    }
+
+    let mut new_paths: HashMap<&str, String> = HashMap::new();
+    for (item, file_name) in &chapters {
+        new_paths.insert(item.path.as_str(), file_name.clone());
+    }
+    for item in &package.items {
+        if Some(item.path.as_str()) == cover_path {
+            let ext = Path::new(&item.path).get_ext().unwrap_or("png".into());
+            new_paths.insert(item.path.as_str(), format!("_cover.{}", ext));
+        } else if !new_paths.contains_key(item.path.as_str()) {
+            new_paths.insert(item.path.as_str(), package.rel_path(&item.path).to_string());
+        }
+    }
+
+    // copy resources
+    for item in &package.items {
+        if is_content_document(&item.media_type) || item.media_type == "application/x-dtbncx+xml" {
+            if !chapters.iter().any(|(c, _)| c.id == item.id)
+                && !nav_ids.contains(&item.id.as_str())
+            {
+                info!("skipping non-spine content document {}", item.path);
+            }
+            continue;
+        }
+        let bytes = read_bytes(&mut archive, &item.path)?;
+        let new_path = &new_paths[item.path.as_str()];
+        let dest = if is_template_resource(&item.media_type) {
+            template_dir.join(new_path)
+        } else {
+            source_dir.join(new_path)
+        };
+        if dest.exists() {
+            println!(
+                "warning: {} already exists, not overwritten",
+                dest.display()
+            );
+            continue;
+        }
+        info!("import-> {}\t{}", item.path, dest.display());
+        write_file(&dest, &bytes)?;
+    }
+
+    // convert chapters
+    let mut chapter_count = 0;
+    for (item, file_name) in &chapters {
+        let xhtml = read_string(&mut archive, &item.path)?;
+        let body = markdown::xhtml_to_markdown(&xhtml, |href| {
+            if is_external(href) || href.starts_with('#') {
+                return href.to_string();
+            }
+            let (path, fragment) = resolve_href(&item.path, href);
+            match new_paths.get(path.as_str()) {
+                // markdown link destinations can't contain spaces
+                Some(new_path) => format!("{}{}", new_path.replace(' ', "%20"), fragment),
+                None => href.to_string(),
+            }
+        })
+        .map_err(|e| Error::Epub(format!("converting {}: {}", item.path, e)))?;
+
+        let is_title_page = file_name == "_title.md";
+        let fallback_title;
+        let title = match titles.get(&item.path) {
+            Some(title) => title.as_str(),
+            None => match markdown::document_title(&xhtml) {
+                Some(title) => {
+                    fallback_title = title;
+                    fallback_title.as_str()
+                }
+                None if is_title_page => "Title Page",
+                None => {
+                    fallback_title = format!("Chapter {}", chapter_count + 1);
+                    fallback_title.as_str()
+                }
+            },
+        };
+        let order = if is_title_page {
+            None
+        } else {
+            chapter_count += 1;
+            Some(chapter_count)
+        };
+        let front_matter = serde_yaml::to_string(&ChapterFrontMatter { title, order })
+            .map_err(|e| Error::Epub(format!("front matter for {}: {}", item.path, e)))?;
+        let dest = source_dir.join(file_name);
+        println!(
+            "converting {}\tto {},\ttitle: {}",
+            item.path,
+            dest.display(),
+            title
+        );
+        write_file(
+            &dest,
+            format!("---\n{}---\n\n{}", front_matter, body).as_bytes(),
+        )?;
+    }
+
+    println!(
+        "imported '{}' by {}: {} chapters",
+        package.title.as_deref().unwrap_or("untitled"),
+        package.author.as_deref().unwrap_or("unknown author"),
+        chapter_count
+    );
+    Ok(chapter_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_href() {
+        let base = "OEBPS/Text/ch1.xhtml";
+        assert_eq!(
+            resolve_href(base, "../Images/a%20b.png"),
+            ("OEBPS/Images/a b.png".to_string(), "".to_string())
+        );
+        assert_eq!(
+            resolve_href(base, "ch2.xhtml#sec"),
+            ("OEBPS/Text/ch2.xhtml".to_string(), "#sec".to_string())
+        );
+        assert_eq!(
+            resolve_href("content.opf", "ch1.xhtml"),
+            ("ch1.xhtml".to_string(), "".to_string())
+        );
+    }
+
+    fn xhtml(title: &str, body: &str) -> String {
+        format!(
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\"><head><title>{}</title></head>\
+             <body>{}</body></html>",
+            title, body
+        )
+    }
+
+    // epub with a package of `items` (id, href) in spine order, and `files`
+    fn write_epub(epub_path: &Path, items: &[(&str, &str)], guide: &str, files: &[(&str, String)]) {
+        use std::io::Write;
+        let mut zip = zip::ZipWriter::new(fs::File::create(epub_path).unwrap());
+        let manifest: String = items
+            .iter()
+            .map(|(id, href)| {
+                format!(
+                    "<item id=\"{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>",
+                    id, href
+                )
+            })
+            .collect();
+        let spine: String = items
+            .iter()
+            .map(|(id, _)| format!("<itemref idref=\"{}\"/>", id))
+            .collect();
+        let package = format!(
+            "<package><metadata><title>Book</title></metadata><manifest>{}</manifest>\
+             <spine>{}</spine><guide>{}</guide></package>",
+            manifest, spine, guide
+        );
+        let container =
+            "<container><rootfiles><rootfile full-path=\"content.opf\"/></rootfiles></container>";
+        for (name, text) in [
+            ("META-INF/container.xml", container),
+            ("content.opf", package.as_str()),
+        ]
+        .into_iter()
+        .chain(files.iter().map(|(name, text)| (*name, text.as_str())))
+        {
+            zip.start_file(name, Default::default()).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_import_title_page_first() {
+        let dir = std::env::temp_dir().join(format!("webgenr-import-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let epub_path = dir.join("book.epub");
+        write_epub(
+            &epub_path,
+            &[("title", "title.xhtml"), ("ch1", "ch1.xhtml")],
+            "<reference type=\"title-page\" href=\"title.xhtml\"/>",
+            &[
+                ("title.xhtml", xhtml("Book", "<p>Book</p>")),
+                ("ch1.xhtml", xhtml("One", "<p>One</p>")),
+            ],
+        );
+
+        let (markdown, templates) = (dir.join("markdown"), dir.join("templates"));
+        assert_eq!(import_epub(&epub_path, &markdown, &templates).unwrap(), 1);
+        let web = Web::new(&markdown, &dir.join("_website"), &templates).unwrap();
+        let spine: Vec<_> = web
+            .book_plan()
+            .unwrap()
+            .entries
+            .into_iter()
+            .filter_map(|entry| Some((entry.spine?, entry.source.file_name()?.to_owned())))
+            .collect();
+        assert_eq!(spine, [(1, "_title.md".into()), (2, "02-ch1.md".into())]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_links_to_book_chapters() {
+        let dir = std::env::temp_dir().join(format!("webgenr-import-links-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let epub_path = dir.join("book.epub");
+        write_epub(
+            &epub_path,
+            &[("ch1", "ch1.xhtml"), ("ch2", "ch2.xhtml")],
+            "",
+            &[
+                (
+                    "ch1.xhtml",
+                    xhtml("One", "<p>See <a href=\"ch2.xhtml#end\">two</a></p>"),
+                ),
+                ("ch2.xhtml", xhtml("Two", "<p id=\"end\">Two</p>")),
+            ],
+        );
+
+        let (markdown, templates) = (dir.join("markdown"), dir.join("templates"));
+        assert_eq!(import_epub(&epub_path, &markdown, &templates).unwrap(), 2);
+        let web = Web::new(&markdown, &dir.join("_website"), &templates).unwrap();
+        let chapter = crate::Document::new(markdown.join("01-ch1.md")).unwrap();
+        let mut html = Vec::new();
+        chapter
+            .write_page(&web, crate::document::Format::Epub, &mut html)
+            .unwrap();
+        let html = String::from_utf8(html).unwrap();
+        assert!(html.contains("href=\"02-ch2.xhtml#end\""), "{}", html);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,49 @@
+mod config;
+pub use self::config::Config;
+
+mod data;
+
 mod document;
-pub use self::document::Document;
+pub use self::document::{Document, Format};
+
+mod error;
+pub use self::error::{Error, Result};
+
+mod exclude;
+
+mod helpers;
+
+mod import;
+pub use self::import::import_epub;
+
+mod manifest;
+
+mod nav;
+
+mod permalink;
+
+mod plan;
+pub use self::plan::{Action, BuildPlan, PlanEntry};
+
+mod schema;
+pub use self::schema::{FieldType, Schema};
 
-mod web;
 mod util;
+
+mod web;
 pub use self::web::Web;
 
+mod watch;
+pub use self::watch::watch;
+
+mod report;
+pub use self::report::{BuildReport, Counts, DocumentReport, Phase, Warning};
+
+mod section;
+
+mod serve;
+pub use self::serve::serve;
+
 extern crate pretty_env_logger;
 
 #[macro_use]
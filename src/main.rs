@@ -1,6 +1,6 @@
-use anyhow::Result;
-use clap::{AppSettings, Parser};
-use webgenr::Web;
+use clap::{AppSettings, Args, Parser, Subcommand};
+use std::path::PathBuf;
+use webgenr::{Config, Error, Result, Web};
 
 extern crate pretty_env_logger;
 
@@ -8,6 +8,18 @@ extern crate pretty_env_logger;
 #[clap(author, version, about, long_about = None)]
 #[clap(global_setting(AppSettings::DeriveDisplayOrder))]
 struct Cli {
+    #[clap(flatten)]
+    site: SiteArgs,
+
+    #[clap(long, short, action)]
+    book: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Args)]
+struct SiteArgs {
     /// directory path for markdown source files
     #[clap(short, long, value_parser, default_value = "markdown")]
     inpath: String,
@@ -20,30 +32,212 @@ struct Cli {
     #[clap(short, long, value_parser, default_value = "templates")]
     templatedir: String,
 
-    #[clap(long, short, action)]
-    book: bool,
+    /// number of threads used to generate the website (default: one per cpu)
+    #[clap(short, long, value_parser, default_value = "0")]
+    jobs: usize,
+
+    /// allow an output directory that contains the source or template
+    /// directory, and delete everything in it when cleaning (unless it
+    /// contains them)
+    #[clap(long, action)]
+    force: bool,
+
+    /// include documents with `draft: true` in their front matter
+    #[clap(long, action)]
+    drafts: bool,
+
+    /// include documents with a `publish_date` in the future
+    #[clap(long, action)]
+    future: bool,
+
+    /// write a json build report to this file
+    #[clap(long, value_parser)]
+    report: Option<String>,
+
+    /// site settings (default: webgenr.yaml, if it exists)
+    #[clap(short, long, value_parser)]
+    config: Option<String>,
+}
+
+impl SiteArgs {
+    // `read_only` doesn't create the source or template directory, for
+    // commands that don't generate anything
+    fn web(&self, read_only: bool) -> Result<Web<'static>> {
+        let config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::load_default()?,
+        };
+        let open = match read_only {
+            true => Web::read_only,
+            false => Web::with_config,
+        };
+        let mut web = open(&self.inpath, &self.outpath, &self.templatedir, config)?;
+        web.jobs = self.jobs;
+        web.force = self.force;
+        web.drafts = self.drafts;
+        web.future = self.future;
+        web.report_path = self.report.as_ref().map(PathBuf::from);
+        Ok(web)
+    }
 }
 
-fn process_files(cli: Cli) -> Result<()> {
-    println!("processing source files from:\t{}", &cli.inpath);
-    let mut web = Web::new(&cli.inpath, &cli.outpath, &cli.templatedir)?;
-    if cli.book {
+#[derive(Subcommand)]
+enum Command {
+    /// build the website (or book), same as running without a subcommand
+    Build {
+        #[clap(flatten)]
+        site: SiteArgs,
+
+        #[clap(long, short, action)]
+        book: bool,
+
+        /// list what the build would do without writing any files
+        #[clap(short = 'n', long, action)]
+        dry_run: bool,
+
+        /// print the --dry-run build plan as json
+        #[clap(long, action, requires = "dry-run")]
+        json: bool,
+    },
+    /// convert an existing epub into a markdown source directory
+    ImportEpub {
+        /// path to the .epub file
+        #[clap(value_parser)]
+        epub: String,
+
+        /// destination path for markdown source files
+        #[clap(short, long, value_parser, default_value = "markdown")]
+        outpath: String,
+
+        /// directory path for template files (stylesheets and fonts)
+        #[clap(short, long, value_parser, default_value = "templates")]
+        templatedir: String,
+    },
+    /// build, then rebuild whenever source or template files change
+    Watch {
+        #[clap(flatten)]
+        site: SiteArgs,
+
+        #[clap(long, short, action)]
+        book: bool,
+    },
+    /// build the website and serve it locally, reloading pages when files change
+    Serve {
+        #[clap(flatten)]
+        site: SiteArgs,
+
+        /// port for the local http server
+        #[clap(short, long, value_parser, default_value = "8000")]
+        port: u16,
+    },
+    /// delete files generated by previous builds from the output directory
+    Clean {
+        #[clap(flatten)]
+        site: SiteArgs,
+
+        /// only list the files that would be deleted
+        #[clap(short = 'n', long, action)]
+        dry_run: bool,
+    },
+}
+
+fn process_files(site: &SiteArgs, book: bool) -> Result<()> {
+    println!("processing source files from:\t{}", &site.inpath);
+    let mut web = site.web(false)?;
+    if book {
         web.gen_book()?;
         println!("book created!");
     } else {
         let count = web.gen_website()?;
         if count > 0 {
-            println!("success! see output files:\t{}", &cli.outpath);
+            println!("success! see output files:\t{}", &site.outpath);
         }
     }
     Ok(())
 }
 
+fn print_plan(site: &SiteArgs, book: bool, json: bool) -> Result<()> {
+    let web = site.web(true)?;
+    let plan = if book {
+        web.book_plan()?
+    } else {
+        web.website_plan()?
+    };
+    if json {
+        let json =
+            serde_json::to_string_pretty(&plan).map_err(|e| Error::io("stdout", e.into()))?;
+        println!("{}", json);
+    } else {
+        println!("{}", plan);
+    }
+    Ok(())
+}
+
+fn clean(site: &SiteArgs, dry_run: bool) -> Result<()> {
+    let files = site.web(true)?.clean(dry_run)?;
+    let action = if dry_run { "would remove" } else { "removed" };
+    for file in files.iter() {
+        println!("{}\t{}", action, file.display());
+    }
+    println!("{} {} files from {}", action, files.len(), &site.outpath);
+    Ok(())
+}
+
+fn import_epub(epub: &str, outpath: &str, templatedir: &str) -> Result<()> {
+    println!("importing epub:\t{}", epub);
+    webgenr::import_epub(epub, outpath, templatedir)?;
+    println!("success! see source files:\t{}", outpath);
+    Ok(())
+}
+
 fn main() {
     pretty_env_logger::init();
     let cli = Cli::parse();
 
-    if let Err(e) = process_files(cli) {
-        println!("Error processing files: {:#?}", e);
+    let result = match &cli.command {
+        Some(Command::Build {
+            site,
+            book,
+            dry_run,
+            json,
+        }) => {
+            if *dry_run {
+                print_plan(site, *book, *json)
+            } else {
+                process_files(site, *book)
+            }
+        }
+        Some(Command::ImportEpub {
+            epub,
+            outpath,
+            templatedir,
+        }) => import_epub(epub, outpath, templatedir),
+        Some(Command::Watch { site, book }) => {
+            site.web(false).and_then(|web| webgenr::watch(web, *book))
+        }
+        Some(Command::Serve { site, port }) => {
+            site.web(false).and_then(|web| webgenr::serve(web, *port))
+        }
+        Some(Command::Clean { site, dry_run }) => clean(site, *dry_run),
+        None => process_files(&cli.site, cli.book),
+    };
+    if let Err(e) = result {
+        eprintln!("Error processing files: {}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+// exit status for each kind of error, following sysexits.h
+fn exit_code(e: &Error) -> i32 {
+    match e.root() {
+        // EX_DATAERR
+        Error::FrontMatter { .. }
+        | Error::Validation(_)
+        | Error::Template { .. }
+        | Error::Data { .. }
+        | Error::Epub(_) => 65,
+        Error::Io { .. } => 74,      // EX_IOERR
+        Error::Config(_) => 78,      // EX_CONFIG
+        Error::Document { .. } => 1, // not returned by root()
     }
 }
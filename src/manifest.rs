@@ -0,0 +1,217 @@
+//-- build manifest -------------------------------------------------------
+// records what the previous website build generated, so the next build
+// only has to re-render or re-copy what changed since then
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path};
+use std::time::UNIX_EPOCH;
+
+pub const MANIFEST_FILE_NAME: &str = ".webgenr-manifest.json";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileRecord {
+    pub hash: String,
+    pub size: u64,
+    pub modified: u64, // nanoseconds since unix epoch
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SourceRecord {
+    #[serde(flatten)]
+    pub file: FileRecord,
+    pub template: Option<String>,
+    pub output: String, // relative to output directory
+    // hashes of anything else the output depends on, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, String>,
+    // url of each source file a page links to, by key (empty if it's missing)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub links: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+    pub version: String,
+    pub config_hash: String,
+    // keys are paths relative to template directory
+    pub templates: BTreeMap<String, FileRecord>,
+    // template files copied as-is, output has same relative path
+    pub assets: BTreeMap<String, FileRecord>,
+    // keys are paths relative to source directory
+    pub sources: BTreeMap<String, SourceRecord>,
+    // generated section pages, keyed by source directory
+    #[serde(default)]
+    pub sections: BTreeMap<String, String>,
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// manifest keys always use '/' so they are the same on every platform
+pub fn path_key<P: AsRef<Path>>(rel_path: P) -> String {
+    rel_path
+        .as_ref()
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl FileRecord {
+    // hash file contents, unless size and modification time match
+    // `previous`, then we trust that content hasn't changed (which saves
+    // reading large media files on every build)
+    pub fn new<P: AsRef<Path>>(path: P, previous: Option<&FileRecord>) -> std::io::Result<Self> {
+        let metadata = fs::metadata(&path)?;
+        let size = metadata.len();
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .and_then(|duration| u64::try_from(duration.as_nanos()).ok())
+            .unwrap_or_default();
+        if let Some(previous) = previous {
+            if previous.size == size && previous.modified == modified && modified != 0 {
+                return Ok(previous.clone());
+            }
+        }
+
+        let mut reader = BufReader::new(fs::File::open(&path)?);
+        let mut hasher = Sha256::new();
+        let mut buffer = [0; 64 * 1024];
+        loop {
+            let count = reader.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            hasher.update(&buffer[..count]);
+        }
+        Ok(FileRecord {
+            hash: to_hex(&hasher.finalize()),
+            size,
+            modified,
+        })
+    }
+
+    pub fn same_content(&self, other: Option<&FileRecord>) -> bool {
+        other.map(|other| other.hash == self.hash).unwrap_or(false)
+    }
+}
+
+impl Manifest {
+    pub fn new(config_hash: String) -> Self {
+        Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            config_hash,
+            ..Default::default()
+        }
+    }
+
+    // manifest from `out_dir` written by any build, None if there isn't one
+    pub fn read<P: AsRef<Path>>(out_dir: P) -> Option<Self> {
+        let path = out_dir.as_ref().join(MANIFEST_FILE_NAME);
+        let text = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<Manifest>(&text) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                println!("warning: ignoring invalid {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    // previous manifest from `out_dir`, None if there isn't one or it was
+    // written by another version of webgenr or with different settings
+    pub fn load<P: AsRef<Path>>(out_dir: P, config_hash: &str) -> Option<Self> {
+        let manifest = Self::read(&out_dir)?;
+        if manifest.version == env!("CARGO_PKG_VERSION") && manifest.config_hash == config_hash {
+            Some(manifest)
+        } else {
+            info!(
+                "build settings changed, ignoring {}",
+                out_dir.as_ref().join(MANIFEST_FILE_NAME).display()
+            );
+            None
+        }
+    }
+
+    // every generated file, relative to the output directory
+    pub fn outputs(&self) -> BTreeSet<&str> {
+        self.sources
+            .values()
+            .map(|record| record.output.as_str())
+            .chain(self.assets.keys().map(|key| key.as_str()))
+            .chain(self.sections.values().map(|output| output.as_str()))
+            .collect()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, out_dir: P) -> std::io::Result<()> {
+        let path = out_dir.as_ref().join(MANIFEST_FILE_NAME);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+// remove a generated file and any directories that are left empty,
+// stopping at `out_dir`
+pub fn remove_output<P: AsRef<Path>>(out_dir: P, rel_path: &str) -> std::io::Result<()> {
+    let out_dir = out_dir.as_ref();
+    // never follow a manifest entry out of `out_dir`
+    if !Path::new(rel_path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        println!(
+            "warning: not removing {}, outside of output directory",
+            rel_path
+        );
+        return Ok(());
+    }
+    let path = out_dir.join(rel_path);
+    if path.is_file() {
+        info!("remove-> {}", path.display());
+        fs::remove_file(&path)?;
+    }
+    let mut dir = path.parent();
+    while let Some(parent) = dir {
+        if parent == out_dir || !parent.starts_with(out_dir) || fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_key() {
+        assert_eq!(path_key(Path::new("notes/one.md")), "notes/one.md");
+        assert_eq!(path_key(Path::new("index.md")), "index.md");
+    }
+
+    #[test]
+    fn test_same_content() {
+        let record = FileRecord {
+            hash: hash_bytes(b"hello"),
+            size: 5,
+            modified: 1,
+        };
+        let mut other = record.clone();
+        other.modified = 2;
+        assert!(record.same_content(Some(&other)));
+        other.hash = hash_bytes(b"world");
+        assert!(!record.same_content(Some(&other)));
+        assert!(!record.same_content(None));
+    }
+}
@@ -0,0 +1,178 @@
+//-- navigation ------------------------------------------------------------
+// the structure of the website for menus: a section for every directory
+// (its page is the directory's `index.md`) with the pages and sections in
+// it, ordered by front matter `weight`, then by file name
+
+use serde_json::{json, Value};
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NavNode {
+    // file or directory name, for ordering
+    name: String,
+    title: String,
+    // None for a directory without `index.md`
+    url: Option<String>,
+    weight: Option<i64>,
+    // a directory, even if there are no pages in it yet
+    is_section: bool,
+    children: Vec<NavNode>,
+}
+
+// a page that is part of the website, `rel_path` is relative to the
+// source directory
+pub struct NavPage<'a> {
+    pub rel_path: &'a Path,
+    pub title: Option<&'a str>,
+    pub url: String,
+    pub weight: Option<i64>,
+}
+
+impl NavNode {
+    pub fn new<'a, I: IntoIterator<Item = NavPage<'a>>>(pages: I) -> Self {
+        let mut root = NavNode {
+            is_section: true,
+            ..Default::default()
+        };
+        for page in pages {
+            let dirs = page
+                .rel_path
+                .parent()
+                .into_iter()
+                .flat_map(|dir| dir.iter());
+            let mut section = &mut root;
+            for dir in dirs {
+                let name = dir.to_string_lossy();
+                let index = match section
+                    .children
+                    .iter()
+                    .position(|c| c.is_section && c.name == name)
+                {
+                    Some(index) => index,
+                    None => {
+                        section.children.push(NavNode {
+                            name: name.to_string(),
+                            title: name.to_string(),
+                            is_section: true,
+                            ..Default::default()
+                        });
+                        section.children.len() - 1
+                    }
+                };
+                section = &mut section.children[index];
+            }
+            let stem = page
+                .rel_path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            if stem == "index" {
+                section.title = page.title.map_or(section.title.clone(), String::from);
+                section.url = Some(page.url);
+                section.weight = page.weight;
+            } else {
+                section.children.push(NavNode {
+                    title: page.title.unwrap_or(&stem).to_string(),
+                    name: stem,
+                    url: Some(page.url),
+                    weight: page.weight,
+                    is_section: false,
+                    children: Vec::new(),
+                });
+            }
+        }
+        root.sort();
+        root
+    }
+
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| {
+            (a.weight.is_none(), a.weight, &a.name).cmp(&(b.weight.is_none(), b.weight, &b.name))
+        });
+        for child in self.children.iter_mut() {
+            child.sort();
+        }
+    }
+
+    // the `nav` template variable for the page at `page_url`: `current` is
+    // the page itself, `active` the sections it is in
+    pub fn to_json(&self, page_url: &str) -> Value {
+        let children: Vec<Value> = self
+            .children
+            .iter()
+            .map(|child| child.to_json(page_url))
+            .collect();
+        let current = self.url.as_deref() == Some(page_url);
+        let active = current || children.iter().any(|child| child["active"] == true);
+        json!({
+            "title": self.title,
+            "url": self.url,
+            "current": current,
+            "active": active,
+            "children": children,
+        })
+    }
+
+    // the `breadcrumbs` template variable: title and url of each section
+    // from the site root down to the page at `page_url`, and the page itself
+    pub fn breadcrumbs(&self, page_url: &str) -> Vec<Value> {
+        let crumb = json!({"title": self.title, "url": self.url});
+        if self.url.as_deref() == Some(page_url) {
+            return vec![crumb];
+        }
+        for child in self.children.iter() {
+            let mut crumbs = child.breadcrumbs(page_url);
+            if !crumbs.is_empty() {
+                crumbs.insert(0, crumb);
+                return crumbs;
+            }
+        }
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nav_tree() {
+        let page = |path: &'static str, title, weight| NavPage {
+            rel_path: Path::new(path),
+            title,
+            url: path.replace(".md", ".html"),
+            weight,
+        };
+        let nav = NavNode::new([
+            page("index.md", Some("Home"), None),
+            page("about.md", None, Some(2)),
+            page("notes/index.md", Some("Notes"), Some(1)),
+            page("notes.md", Some("Notes page"), Some(3)),
+            page("notes/one.md", Some("One"), None),
+            page("misc/two.md", Some("Two"), None),
+        ]);
+        let tree = nav.to_json("notes/one.html");
+        assert_eq!(tree["title"], "Home");
+        assert_eq!(tree["active"], true);
+        let titles: Vec<&str> = tree["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|child| child["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, ["Notes", "about", "Notes page", "misc"]);
+        assert_eq!(tree["children"][0]["active"], true);
+        assert_eq!(tree["children"][0]["current"], false);
+        assert_eq!(tree["children"][0]["children"][0]["current"], true);
+        assert_eq!(tree["children"][3]["url"], Value::Null);
+        assert_eq!(
+            nav.breadcrumbs("notes/one.html"),
+            [
+                json!({"title": "Home", "url": "index.html"}),
+                json!({"title": "Notes", "url": "notes/index.html"}),
+                json!({"title": "One", "url": "notes/one.html"}),
+            ]
+        );
+        assert!(nav.breadcrumbs("nope.html").is_empty());
+    }
+}
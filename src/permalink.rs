@@ -0,0 +1,237 @@
+//-- permalinks ------------------------------------------------------------
+// where each page of the website is written, as a url relative to the site
+// root: `notes/one.html`, or `notes/one/` for `notes/one/index.html`
+
+use crate::error::{Error, Result};
+use chrono::{DateTime, Datelike, FixedOffset};
+use std::path::{Component, Path, PathBuf};
+
+const TOKENS: [&str; 5] = [":path", ":slug", ":year", ":month", ":day"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Permalink {
+    // mirrors the source path, `notes/one.md` is `notes/one.html`
+    #[default]
+    Plain,
+    // `notes/one.md` is `notes/one/index.html`
+    Pretty,
+    // like `/:year/:month/:slug/`
+    Pattern(String),
+}
+
+impl std::str::FromStr for Permalink {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "plain" => Ok(Permalink::Plain),
+            "pretty" => Ok(Permalink::Pretty),
+            pattern if !pattern.contains(":slug") => Err(Error::Config(format!(
+                "permalink '{}': expected plain, pretty or a pattern with :slug",
+                pattern
+            ))),
+            pattern => {
+                for segment in pattern.split('/') {
+                    if segment == ".." {
+                        return Err(Error::Config(format!(
+                            "permalink '{}': '..' isn't allowed",
+                            pattern
+                        )));
+                    }
+                    let mut rest = segment;
+                    while let Some(start) = rest.find(':') {
+                        rest = &rest[start..];
+                        match TOKENS.iter().find(|token| rest.starts_with(*token)) {
+                            Some(token) => rest = &rest[token.len()..],
+                            None => {
+                                return Err(Error::Config(format!(
+                                    "permalink '{}': unknown token '{}', expected one of {}",
+                                    pattern,
+                                    rest,
+                                    TOKENS.join(", ")
+                                )))
+                            }
+                        }
+                    }
+                }
+                Ok(Permalink::Pattern(pattern.to_string()))
+            }
+        }
+    }
+}
+
+impl Permalink {
+    // url of the page for the markdown file at `rel_path` (relative to the
+    // source directory), `slug` replaces the file name, patterns with
+    // date tokens fall back to pretty urls for pages without a `date`
+    pub fn page_url(
+        &self,
+        rel_path: &Path,
+        slug: Option<&str>,
+        date: Option<DateTime<FixedOffset>>,
+    ) -> String {
+        let dir = path_url(rel_path.parent().unwrap_or(Path::new("")));
+        let stem = rel_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        // `index.md` is the page for its directory, unless it has a slug
+        let slug = match slug {
+            Some(slug) => slug,
+            None if stem == "index" => return join_url(&dir, "index.html"),
+            None => stem.as_ref(),
+        };
+        match self {
+            Permalink::Plain => join_url(&dir, &format!("{}.html", slug)),
+            Permalink::Pretty => join_url(&dir, &format!("{}/", slug)),
+            Permalink::Pattern(pattern) => {
+                let uses_date = [":year", ":month", ":day"]
+                    .iter()
+                    .any(|token| pattern.contains(token));
+                let url = pattern.replace(":path", &dir).replace(":slug", slug);
+                let url = match date {
+                    Some(date) => url
+                        .replace(":year", &format!("{:04}", date.year()))
+                        .replace(":month", &format!("{:02}", date.month()))
+                        .replace(":day", &format!("{:02}", date.day())),
+                    None if uses_date => {
+                        return Permalink::Pretty.page_url(rel_path, Some(slug), None)
+                    }
+                    None => url,
+                };
+                normalize_url(&url)
+            }
+        }
+    }
+}
+
+// front matter `permalink: /about/`, relative to the site root
+pub fn explicit_url(permalink: &str) -> String {
+    normalize_url(permalink)
+}
+
+// leading and repeated slashes removed, without an extension the url is
+// a directory and the page is its `index.html`
+fn normalize_url(url: &str) -> String {
+    let segments: Vec<&str> = url
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+    let mut url = segments.join("/");
+    let is_file = segments
+        .last()
+        .is_some_and(|last| Path::new(last).extension().is_some());
+    if !is_file && !url.is_empty() {
+        url.push('/');
+    }
+    url
+}
+
+fn join_url(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+// path with `/` separators
+pub fn path_url(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// file that is served for `url`
+pub fn url_file(url: &str) -> PathBuf {
+    let mut path: PathBuf = url.split('/').filter(|s| !s.is_empty()).collect();
+    if url.is_empty() || url.ends_with('/') {
+        path.push("index.html");
+    }
+    path
+}
+
+// link from the page at url `from` to url `to`, both relative to the
+// site root, like `../two/` from `notes/one/` to `notes/two/`
+pub fn relative_url(from: &str, to: &str) -> String {
+    let from_dir: Vec<&str> = match from.rfind('/') {
+        Some(end) => from[..end].split('/').collect(),
+        None => Vec::new(),
+    };
+    let to_parts: Vec<&str> = to.split('/').collect();
+    let to_dir = &to_parts[..to_parts.len() - 1];
+    let common = from_dir
+        .iter()
+        .zip(to_dir)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let url = "../".repeat(from_dir.len() - common) + &to_parts[common..].join("/");
+    if url.is_empty() {
+        "./".into()
+    } else {
+        url
+    }
+}
+
+// `a/./b/../c` is `a/c`, without looking at the file system
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_url() {
+        let path = Path::new("notes/one.md");
+        let date = DateTime::parse_from_rfc3339("2023-04-01T09:00:00Z").ok();
+        assert_eq!(
+            Permalink::Plain.page_url(path, None, None),
+            "notes/one.html"
+        );
+        assert_eq!(Permalink::Pretty.page_url(path, None, None), "notes/one/");
+        assert_eq!(
+            Permalink::Pretty.page_url(Path::new("notes/index.md"), None, None),
+            "notes/index.html"
+        );
+        let pattern: Permalink = "/:year/:month/:slug/".parse().unwrap();
+        assert_eq!(
+            pattern.page_url(path, Some("first"), date),
+            "2023/04/first/"
+        );
+        assert_eq!(pattern.page_url(path, None, None), "notes/one/");
+        let pattern: Permalink = "/:path/:slug.html".parse().unwrap();
+        assert_eq!(
+            pattern.page_url(Path::new("one.md"), None, None),
+            "one.html"
+        );
+        assert!("/:yaer/:slug/".parse::<Permalink>().is_err());
+        assert!("prety".parse::<Permalink>().is_err());
+        assert_eq!(explicit_url("/about"), "about/");
+        assert_eq!(url_file("about/"), Path::new("about/index.html"));
+    }
+
+    #[test]
+    fn test_relative_url() {
+        assert_eq!(relative_url("notes/one/", "notes/two/"), "../two/");
+        assert_eq!(relative_url("notes/one.html", "notes/two.html"), "two.html");
+        assert_eq!(relative_url("index.html", "notes/two/"), "notes/two/");
+        assert_eq!(relative_url("notes/one/", "index.html"), "../../index.html");
+        assert_eq!(relative_url("notes/", "notes/"), "./");
+        assert_eq!(
+            normalize_path(Path::new("md/notes/../a/./b.md")),
+            Path::new("md/a/b.md")
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//-- build plan ------------------------------------------------------------
+// what a build would do with each source file, without writing anything
+
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Render, // markdown converted to html with a template
+    Copy,   // copied as-is
+    Skip,   // not written, see `reason`
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Render => "render",
+            Action::Copy => "copy",
+            Action::Skip => "skip",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlanEntry {
+    pub action: Action,
+    pub source: PathBuf,
+    // for websites the output file, for books the path inside the epub
+    pub output: Option<PathBuf>,
+    pub template: Option<String>,
+    pub reason: Option<String>,
+    // book chapter number and position in the epub spine (both from 1)
+    pub chapter: Option<usize>,
+    pub spine: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BuildPlan {
+    pub format: String, // "web" or "epub"
+    pub entries: Vec<PlanEntry>,
+}
+
+impl BuildPlan {
+    pub fn count(&self, action: Action) -> usize {
+        self.entries.iter().filter(|e| e.action == action).count()
+    }
+}
+
+impl fmt::Display for BuildPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in self.entries.iter() {
+            write!(f, "{:<8}{}", entry.action.name(), entry.source.display())?;
+            if let Some(output) = &entry.output {
+                write!(f, " -> {}", output.display())?;
+            }
+            let mut notes = Vec::new();
+            if let Some(template) = &entry.template {
+                notes.push(format!("template: {}", template));
+            }
+            if let Some(chapter) = entry.chapter {
+                notes.push(format!("chapter {}", chapter));
+            }
+            if let Some(spine) = entry.spine {
+                notes.push(format!("spine {}", spine));
+            }
+            if let Some(reason) = &entry.reason {
+                notes.push(reason.clone());
+            }
+            if !notes.is_empty() {
+                write!(f, "  ({})", notes.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} to render, {} to copy, {} to skip",
+            self.count(Action::Render),
+            self.count(Action::Copy),
+            self.count(Action::Skip)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_plan() {
+        let plan = BuildPlan {
+            format: "epub".into(),
+            entries: vec![
+                PlanEntry {
+                    action: Action::Render,
+                    source: PathBuf::from("source/ch1.md"),
+                    output: Some(PathBuf::from("ch1.xhtml")),
+                    template: Some("default".into()),
+                    reason: None,
+                    chapter: Some(1),
+                    spine: Some(2),
+                },
+                PlanEntry {
+                    action: Action::Skip,
+                    source: PathBuf::from("source/web.md"),
+                    output: None,
+                    template: None,
+                    reason: Some("not included in epub".into()),
+                    chapter: None,
+                    spine: None,
+                },
+            ],
+        };
+        assert_eq!(
+            plan.to_string(),
+            "render  source/ch1.md -> ch1.xhtml  (template: default, chapter 1, spine 2)\n\
+             skip    source/web.md  (not included in epub)\n\
+             1 to render, 0 to copy, 1 to skip"
+        );
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["entries"][0]["action"], "render");
+        assert_eq!(json["entries"][1]["output"], serde_json::Value::Null);
+    }
+}
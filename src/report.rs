@@ -0,0 +1,153 @@
+//-- build report ----------------------------------------------------------
+// machine-readable summary of a website or book build: what happened to
+// each source file, warnings and how long each phase took
+
+use crate::plan::Action;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+#[derive(Serialize, Default, Debug, PartialEq, Eq)]
+pub struct Counts {
+    pub documents: usize,
+    pub rendered: usize,
+    pub copied: usize,
+    pub skipped: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Phase {
+    pub name: String,
+    pub millis: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DocumentReport {
+    pub source: PathBuf,
+    // for websites the output file, for books the path inside the epub
+    pub output: Option<PathBuf>,
+    pub action: Action,
+    pub reason: Option<String>,
+    pub size: Option<u64>, // bytes written
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Warning {
+    pub source: PathBuf,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BuildReport {
+    pub version: String,
+    pub format: String, // "web" or "epub"
+    pub output: PathBuf,
+    pub counts: Counts,
+    pub documents: Vec<DocumentReport>,
+    // drafts and documents scheduled for later, which weren't published
+    pub held_back: Vec<PathBuf>,
+    // sorted, so reports of the same build can be compared
+    pub warnings: Vec<Warning>,
+    // timings are the only part that changes between identical builds
+    pub phases: Vec<Phase>,
+    #[serde(skip)]
+    phase_start: Option<(String, Instant)>,
+}
+
+impl BuildReport {
+    pub fn new<P: AsRef<Path>>(format: &str, output: P) -> Self {
+        BuildReport {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            format: format.to_string(),
+            output: output.as_ref().to_path_buf(),
+            counts: Counts::default(),
+            documents: Vec::new(),
+            held_back: Vec::new(),
+            warnings: Vec::new(),
+            phases: Vec::new(),
+            phase_start: None,
+        }
+    }
+
+    // start timing `name`, which ends the current phase
+    pub fn phase(&mut self, name: &str) {
+        self.end_phase();
+        self.phase_start = Some((name.to_string(), Instant::now()));
+    }
+
+    fn end_phase(&mut self) {
+        if let Some((name, start)) = self.phase_start.take() {
+            self.phases.push(Phase {
+                name,
+                millis: start.elapsed().as_secs_f64() * 1000.0,
+            });
+        }
+    }
+
+    pub fn add_document(&mut self, document: DocumentReport) {
+        self.counts.documents += 1;
+        match document.action {
+            Action::Render => self.counts.rendered += 1,
+            Action::Copy => self.counts.copied += 1,
+            Action::Skip => self.counts.skipped += 1,
+        }
+        self.documents.push(document);
+    }
+
+    // end timing and sort warnings
+    pub fn finish(&mut self, mut warnings: Vec<Warning>) {
+        self.end_phase();
+        warnings.sort();
+        self.warnings = warnings;
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_counts() {
+        let mut report = BuildReport::new("web", "_website");
+        report.phase("documents");
+        for (action, reason) in [
+            (Action::Render, None),
+            (Action::Skip, Some("unchanged")),
+            (Action::Copy, None),
+            (Action::Render, None),
+        ] {
+            report.add_document(DocumentReport {
+                source: PathBuf::from("markdown/a.md"),
+                output: None,
+                action,
+                reason: reason.map(String::from),
+                size: None,
+            });
+        }
+        report.finish(vec![
+            Warning {
+                source: PathBuf::from("markdown/b.md"),
+                message: "b".into(),
+            },
+            Warning {
+                source: PathBuf::from("markdown/a.md"),
+                message: "a".into(),
+            },
+        ]);
+        assert_eq!(
+            report.counts,
+            Counts {
+                documents: 4,
+                rendered: 2,
+                copied: 1,
+                skipped: 1
+            }
+        );
+        assert_eq!(report.phases.len(), 1);
+        assert_eq!(report.warnings[0].message, "a");
+    }
+}
@@ -0,0 +1,148 @@
+//-- front matter schemas --------------------------------------------------
+// optional rules in `webgenr.yaml` for the front matter of the documents in
+// a directory, or rendered with a template:
+//   schemas:
+//     - directory: notes
+//       required: [title, date]
+//       fields: {title: string, date: date, tags: list, author: object}
+
+use crate::document::Document;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// variables webgenr uses itself, always allowed (and type checked when the
+// front matter is read)
+const BUILTIN_VARS: [&str; 10] = [
+    "title",
+    "date",
+    "publish_date",
+    "draft",
+    "order",
+    "weight",
+    "tags",
+    "template",
+    "slug",
+    "permalink",
+];
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schema {
+    // relative to the source directory, all documents if not set
+    pub directory: Option<PathBuf>,
+    // only documents rendered with this template
+    pub template: Option<String>,
+    pub required: Vec<String>,
+    // allowed variables and their types, if there are none any variable
+    // is allowed
+    pub fields: BTreeMap<String, FieldType>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    String,
+    Number,
+    Integer,
+    Bool,
+    Date, // a string like `2023-04-01`
+    List,
+    Object,
+    Any,
+}
+
+impl FieldType {
+    fn matches(self, value: &serde_json::Value) -> bool {
+        match self {
+            FieldType::String => value.is_string(),
+            FieldType::Number => value.is_number(),
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Bool => value.is_boolean(),
+            FieldType::Date => value
+                .as_str()
+                .is_some_and(|date| crate::document::parse_date(date).is_some()),
+            FieldType::List => value.is_array(),
+            FieldType::Object => value.is_object(),
+            FieldType::Any => true,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FieldType::String => "a string",
+            FieldType::Number => "a number",
+            FieldType::Integer => "a whole number",
+            FieldType::Bool => "true or false",
+            FieldType::Date => "a date",
+            FieldType::List => "a list",
+            FieldType::Object => "an object",
+            FieldType::Any => "anything",
+        }
+    }
+}
+
+impl Schema {
+    // `rel_path` is relative to the source directory
+    pub fn applies_to(&self, rel_path: &Path, template: Option<&str>) -> bool {
+        self.directory
+            .as_ref()
+            .is_none_or(|directory| rel_path.starts_with(directory))
+            && self
+                .template
+                .as_ref()
+                .is_none_or(|name| template == Some(name.as_str()))
+    }
+
+    // every way `doc` doesn't match, as front matter errors
+    pub fn check(&self, doc: &Document) -> Vec<Error> {
+        let violation = |name: &str, message: String| Error::FrontMatter {
+            path: doc.source_path.clone(),
+            line: doc.var_line(name),
+            column: None,
+            message,
+        };
+        let mut violations = Vec::new();
+        for name in self.required.iter() {
+            if doc.value(name).is_none() {
+                violations.push(violation(name, format!("'{}' is required", name)));
+            }
+        }
+        if self.fields.is_empty() {
+            return violations;
+        }
+        for (name, value) in doc.front_matter().into_iter().flatten() {
+            match self.fields.get(name) {
+                Some(field_type) if !field_type.matches(value) => violations.push(violation(
+                    name,
+                    format!("{}: expected {}, not {}", name, field_type.name(), value),
+                )),
+                None if !BUILTIN_VARS.contains(&name.as_str()) => {
+                    violations.push(violation(name, format!("unknown variable '{}'", name)))
+                }
+                _ => {}
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_applies_to() {
+        let schema: Schema = serde_yaml::from_str(
+            "directory: notes\ntemplate: post\nrequired: [title]\nfields: {tags: list}",
+        )
+        .unwrap();
+        assert!(schema.applies_to(Path::new("notes/one.md"), Some("post")));
+        assert!(!schema.applies_to(Path::new("notes/one.md"), Some("default")));
+        assert!(!schema.applies_to(Path::new("notesx/one.md"), Some("post")));
+        assert!(FieldType::Date.matches(&"2023-04-01".into()));
+        assert!(!FieldType::Integer.matches(&1.5.into()));
+        assert!(serde_yaml::from_str::<Schema>("fields: {a: strng}").is_err());
+    }
+}
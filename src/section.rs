@@ -0,0 +1,213 @@
+//-- section pages ---------------------------------------------------------
+// with `section_pages: true` in the config, every source directory without
+// an index page gets a generated `index.html`, rendered with the `section`
+// template, listing the pages, files and directories in it
+
+use crate::permalink;
+use crate::util::*;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+pub const SECTION_TEMPLATE: &str = "section";
+
+// a document on the website, `rel_path` is relative to the source directory
+pub struct Entry<'a> {
+    pub rel_path: &'a Path,
+    pub url: String,
+    // pages only, files have a `size`
+    pub is_page: bool,
+    pub title: Option<&'a str>,
+    pub date: Option<String>,
+    pub weight: Option<i64>,
+    pub size: Option<u64>,
+}
+
+impl Entry<'_> {
+    fn name(&self) -> String {
+        self.rel_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn stem(&self) -> String {
+        self.rel_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn is_index(&self) -> bool {
+        self.is_page && self.rel_path.file_stem() == Some("index".as_ref())
+    }
+}
+
+// url of the generated page for `dir`
+pub fn url(dir: &Path) -> String {
+    match permalink::path_url(dir).as_str() {
+        "" => "index.html".into(),
+        dir => format!("{}/index.html", dir),
+    }
+}
+
+// directories with something in them and no page at their index url, like
+// `notes` for `notes/one.md` without `notes/index.md`
+pub fn directories(entries: &[Entry]) -> Vec<PathBuf> {
+    let taken: BTreeSet<PathBuf> = entries
+        .iter()
+        .map(|entry| permalink::url_file(&entry.url))
+        .collect();
+    let dirs: BTreeSet<&Path> = entries
+        .iter()
+        .flat_map(|entry| entry.rel_path.ancestors().skip(1))
+        .collect();
+    dirs.into_iter()
+        .filter(|dir| !taken.contains(&permalink::url_file(&url(dir))))
+        .map(Path::to_path_buf)
+        .collect()
+}
+
+// template variables for the section page of `dir`: `pages` and `files`
+// directly in it and its subdirectories as `sections`, `generated` are
+// the directories that get a section page
+pub fn listing(dir: &Path, entries: &[Entry], generated: &[PathBuf]) -> Map<String, Value> {
+    let mut pages: Vec<&Entry> = Vec::new();
+    let mut files: Vec<&Entry> = Vec::new();
+    let mut subdirs: BTreeSet<PathBuf> = BTreeSet::new();
+    for entry in entries {
+        let parent = entry.rel_path.parent().unwrap_or(Path::new(""));
+        if parent == dir {
+            match entry.is_page {
+                true if entry.is_index() => {}
+                true => pages.push(entry),
+                false => files.push(entry),
+            }
+        } else if let Ok(rest) = parent.strip_prefix(dir) {
+            if let Some(first) = rest.iter().next() {
+                subdirs.insert(dir.join(first));
+            }
+        }
+    }
+    let by_weight = |entry: &&Entry| (entry.weight.is_none(), entry.weight, entry.name());
+    pages.sort_by_key(by_weight);
+    files.sort_by_key(|entry| entry.name());
+
+    let mut sections: Vec<(Option<i64>, String, Value)> = subdirs
+        .iter()
+        .map(|subdir| {
+            let name = subdir
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let index = entries
+                .iter()
+                .find(|entry| entry.is_index() && entry.rel_path.parent() == Some(subdir));
+            let (title, url, weight) = match index {
+                Some(index) => (
+                    index.title.map_or(name.clone(), String::from),
+                    Some(index.url.clone()),
+                    index.weight,
+                ),
+                None if generated.contains(subdir) => (name.clone(), Some(url(subdir)), None),
+                None => (name.clone(), None, None),
+            };
+            (weight, name, json!({"title": title, "url": url}))
+        })
+        .collect();
+    sections.sort_by(|a, b| (a.0.is_none(), a.0, &a.1).cmp(&(b.0.is_none(), b.0, &b.1)));
+
+    let mut vars = Map::new();
+    vars.insert(
+        "pages".into(),
+        pages
+            .iter()
+            .map(|page| {
+                json!({
+                    "title": page.title.map_or_else(|| page.stem(), String::from),
+                    "url": page.url,
+                    "date": page.date,
+                })
+            })
+            .collect(),
+    );
+    vars.insert(
+        "files".into(),
+        files.iter().map(|file| file_vars(file)).collect(),
+    );
+    vars.insert(
+        "sections".into(),
+        sections
+            .into_iter()
+            .map(|(_, _, section)| section)
+            .collect(),
+    );
+    vars
+}
+
+// `type` is the first part of the media type, like `audio` for `audio/mpeg`
+fn file_vars(file: &Entry) -> Value {
+    let mimetype = file.rel_path.mimetype();
+    json!({
+        "name": file.name(),
+        "url": file.url,
+        "size": file.size,
+        "size_text": file.size.map(size_text),
+        "mimetype": mimetype.as_ref().map(|mimetype| mimetype.essence_str().to_string()),
+        "type": mimetype.as_ref().map(|mimetype| mimetype.type_().as_str().to_string()),
+    })
+}
+
+// like `700 KB` or `1.2 MB`
+fn size_text(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["bytes", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ if size < 10.0 => format!("{:.1} {}", size, UNITS[unit]),
+        _ => format!("{:.0} {}", size, UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section_listing() {
+        let entry = |path: &'static str, is_page, weight| Entry {
+            rel_path: Path::new(path),
+            url: path.replace(".md", ".html"),
+            is_page,
+            title: None,
+            date: None,
+            weight,
+            size: (!is_page).then_some(700_000),
+        };
+        let entries = [
+            entry("index.md", true, None),
+            entry("notes/one.md", true, None),
+            entry("notes/two.md", true, Some(1)),
+            entry("notes/talk.mp3", false, None),
+            entry("notes/old/three.md", true, None),
+            entry("notes/new/index.md", true, None),
+        ];
+        let dirs = directories(&entries);
+        assert_eq!(dirs, [PathBuf::from("notes"), PathBuf::from("notes/old")]);
+        assert_eq!(url(Path::new("notes/old")), "notes/old/index.html");
+
+        let vars = Value::Object(listing(Path::new("notes"), &entries, &dirs));
+        assert_eq!(vars["pages"][0]["title"], "two");
+        assert_eq!(vars["pages"][1]["url"], "notes/one.html");
+        assert_eq!(vars["files"][0]["type"], "audio");
+        assert_eq!(vars["files"][0]["size_text"], "700 KB");
+        assert_eq!(vars["sections"][0]["url"], "notes/new/index.html");
+        assert_eq!(vars["sections"][1]["url"], "notes/old/index.html");
+        assert_eq!(size_text(1_234_567), "1.2 MB");
+    }
+}
@@ -0,0 +1,293 @@
+//-- local preview server -------------------------------------------------
+// serves the generated website over http, rebuilding when files change,
+// and reloads open pages in the browser after each rebuild
+
+use crate::error::{Error, Result};
+use crate::util::*;
+use crate::watch::watch_changes;
+use crate::Web;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tiny_http::{Header, Method, Request, Response, StatusCode};
+
+const BUILD_ID_URL: &str = "/__webgenr/build";
+
+// polls for the build id and reloads the page when it changes
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+  var build = null;
+  setInterval(function () {
+    fetch("/__webgenr/build").then(function (response) {
+      return response.text();
+    }).then(function (id) {
+      if (build !== null && id !== build) { location.reload(); }
+      build = id;
+    }).catch(function () {});
+  }, 1000);
+})();
+</script>
+"#;
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    Partial(u64, u64), // inclusive start and end
+    Unsatisfiable,
+}
+
+// parse a `Range: bytes=...` header value for a file of `len` bytes,
+// multiple ranges are not supported, so the full file is returned
+fn parse_range(value: &str, len: u64) -> ByteRange {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(parts) => parts,
+        None => return ByteRange::Full,
+    };
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return ByteRange::Full,
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, "") => match start.parse::<u64>() {
+            Ok(start) => (start, len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+            _ => return ByteRange::Full,
+        },
+    };
+    if len == 0 || range.0 >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(range.0, range.1)
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field, value).expect("valid header")
+}
+
+fn inject_live_reload(html: &str) -> String {
+    match html.rfind("</body>") {
+        Some(i) => format!("{}{}{}", &html[..i], LIVE_RELOAD_SCRIPT, &html[i..]),
+        None => format!("{}{}", html, LIVE_RELOAD_SCRIPT),
+    }
+}
+
+// map a request url to a file (or directory) in `root`, None if it would
+// escape `root`
+fn file_path(root: &Path, url: &str) -> Option<PathBuf> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let decoded = percent_decode(path);
+    let mut file_path = root.to_path_buf();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => file_path.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(file_path)
+}
+
+// `/notes/one` is redirected to `/notes/one/` when it's a directory, so
+// relative links in its `index.html` are resolved inside the directory
+fn directory_url(url: &str) -> Option<String> {
+    let (path, rest) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+    (!path.ends_with('/')).then(|| format!("{}/{}", path, rest))
+}
+
+fn respond_file(request: Request, path: &Path) -> std::io::Result<()> {
+    let mimetype = path
+        .mimetype()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+        .to_string();
+    if mimetype == mime::TEXT_HTML.as_ref() {
+        let html = inject_live_reload(&fs::read_to_string(path)?);
+        let response = Response::from_string(html)
+            .with_header(header("Content-Type", "text/html; charset=utf-8"));
+        request.respond(response)?;
+        return Ok(());
+    }
+
+    let mut file = fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    let range = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Range"))
+        .map(|h| parse_range(h.value.as_str(), len))
+        .unwrap_or(ByteRange::Full);
+    let headers = vec![
+        header("Content-Type", &mimetype),
+        header("Accept-Ranges", "bytes"),
+    ];
+    match range {
+        ByteRange::Full => {
+            let response = Response::new(StatusCode(200), headers, file, Some(len as usize), None);
+            request.respond(response)?;
+        }
+        ByteRange::Partial(start, end) => {
+            file.seek(SeekFrom::Start(start))?;
+            let count = end - start + 1;
+            let mut response = Response::new(
+                StatusCode(206),
+                headers,
+                file.take(count),
+                Some(count as usize),
+                None,
+            );
+            response.add_header(header(
+                "Content-Range",
+                &format!("bytes {}-{}/{}", start, end, len),
+            ));
+            request.respond(response)?;
+        }
+        ByteRange::Unsatisfiable => {
+            let response = Response::empty(StatusCode(416))
+                .with_header(header("Content-Range", &format!("bytes */{}", len)));
+            request.respond(response)?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(request: Request, root: &Path, build_id: &AtomicUsize) -> std::io::Result<()> {
+    info!("{} {}", request.method(), request.url());
+    if request.method() != &Method::Get && request.method() != &Method::Head {
+        request.respond(Response::empty(StatusCode(405)))?;
+        return Ok(());
+    }
+    if request.url() == BUILD_ID_URL {
+        let id = build_id.load(Ordering::SeqCst).to_string();
+        request
+            .respond(Response::from_string(id).with_header(header("Cache-Control", "no-store")))?;
+        return Ok(());
+    }
+    match file_path(root, request.url()) {
+        Some(path) if path.is_dir() => match directory_url(request.url()) {
+            Some(location) => {
+                let response =
+                    Response::empty(StatusCode(301)).with_header(header("Location", &location));
+                request.respond(response)?;
+                Ok(())
+            }
+            None if path.join("index.html").is_file() => {
+                respond_file(request, &path.join("index.html"))
+            }
+            None => {
+                request.respond(Response::from_string("not found").with_status_code(404))?;
+                Ok(())
+            }
+        },
+        Some(path) if path.is_file() => respond_file(request, &path),
+        _ => {
+            request.respond(Response::from_string("not found").with_status_code(404))?;
+            Ok(())
+        }
+    }
+}
+
+// build the website and serve its output directory at
+// http://localhost:`port`, rebuilding and reloading pages in the
+// browser whenever files change, drafts are included as a preview
+pub fn serve(mut web: Web<'static>, port: u16) -> Result<()> {
+    web.drafts = true;
+    if let Err(e) = web.gen_website() {
+        println!("Error processing files: {}", e);
+    }
+
+    let server = tiny_http::Server::http(("127.0.0.1", port))
+        .map_err(|e| Error::Config(format!("starting server on port {}: {}", port, e)))?;
+    println!(
+        "serving {} at http://localhost:{}/",
+        web.out_path.display(),
+        port
+    );
+    let root = web.out_path.clone();
+
+    let build_id = Arc::new(AtomicUsize::new(0));
+    let watcher_build_id = build_id.clone();
+    std::thread::spawn(move || {
+        let result = watch_changes(web, false, || {
+            watcher_build_id.fetch_add(1, Ordering::SeqCst);
+        });
+        if let Err(e) = result {
+            println!("error watching files: {}", e);
+        }
+    });
+
+    for request in server.incoming_requests() {
+        // one thread per request, so a long audio download won't block pages
+        let root = root.clone();
+        let build_id = build_id.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_request(request, &root, &build_id) {
+                info!("request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(
+            parse_range("bytes=500-", 1000),
+            ByteRange::Partial(500, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=900-2000", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), ByteRange::Full);
+        assert_eq!(parse_range("items=0-1", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn test_file_path() {
+        let root = Path::new("_website");
+        assert_eq!(
+            file_path(root, "/notes/one%20two.html?x=1"),
+            Some(PathBuf::from("_website/notes/one two.html"))
+        );
+        assert_eq!(file_path(root, "/../secret"), None);
+    }
+
+    #[test]
+    fn test_directory_url() {
+        assert_eq!(directory_url("/notes/one"), Some("/notes/one/".into()));
+        assert_eq!(
+            directory_url("/notes/one?x=1"),
+            Some("/notes/one/?x=1".into())
+        );
+        assert_eq!(directory_url("/notes/one/"), None);
+        assert_eq!(directory_url("/"), None);
+    }
+
+    #[test]
+    fn test_inject_live_reload() {
+        let html = inject_live_reload("<body>hi</body>");
+        assert!(html.starts_with("<body>hi<script>"));
+        assert!(html.ends_with("</script>\n</body>"));
+    }
+}
@@ -4,5 +4,5 @@ pub use self::dir_entry::DirEntryExt;
 pub mod path;
 pub use self::path::PathExt;
 
-pub mod cowstr;
-pub use self::cowstr::CowStrExt;
+pub mod url;
+pub use self::url::percent_decode;
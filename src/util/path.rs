@@ -2,7 +2,10 @@
 // extensons to Path struct and related helper functions
 
 use mime::Mime;
-use std::{borrow::Cow, path::Path};
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+};
 
 pub trait PathExt {
     // given a path, ensure that all parent directories of that path exist
@@ -12,6 +15,10 @@ pub trait PathExt {
     fn get_ext_str(&self) -> Option<&str>;
     fn mimetype(&self) -> Option<Mime>;
     fn is_markdown(&self) -> bool;
+    // canonical path if it exists, otherwise joined to current directory
+    fn absolute(&self) -> PathBuf;
+    // both paths exist and name the same file
+    fn is_same_file(&self, other: &Path) -> bool;
 }
 
 impl PathExt for Path {
@@ -58,6 +65,21 @@ impl PathExt for Path {
         }
         false
     }
+    fn absolute(&self) -> PathBuf {
+        match std::fs::canonicalize(self) {
+            Ok(path) => path,
+            Err(_) => match std::env::current_dir() {
+                Ok(dir) => dir.join(self),
+                Err(_) => self.to_path_buf(),
+            },
+        }
+    }
+    fn is_same_file(&self, other: &Path) -> bool {
+        match (std::fs::canonicalize(self), std::fs::canonicalize(other)) {
+            (Ok(path), Ok(other)) => path == other,
+            _ => false,
+        }
+    }
 }
 
 #[cfg(test)]
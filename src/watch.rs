@@ -0,0 +1,127 @@
+//-- watch mode ------------------------------------------------------------
+// rebuild whenever files in the source, template or data directories change
+
+use crate::error::{Error, Result};
+use crate::util::*;
+use crate::Web;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::Duration;
+
+// editors often write a file several times when saving, wait until
+// things are quiet for this long before rebuilding
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+// paths that changed since the last build, grouped by what they affect
+#[derive(Default)]
+struct Changes {
+    sources: bool,
+    templates: bool,
+}
+
+// notify errors are reported as io errors on the watched directory
+fn watch_error<P: AsRef<Path>>(path: P) -> impl Fn(notify::Error) -> Error {
+    let path = path.as_ref().to_path_buf();
+    move |e| Error::io(&path, std::io::Error::other(e))
+}
+
+fn build(web: &mut Web, book: bool) -> Result<()> {
+    if book {
+        web.gen_book()?;
+        println!("book created!");
+    } else {
+        web.gen_website()?;
+    }
+    Ok(())
+}
+
+// build once, then watch the source and template directories of `web`
+// and rebuild on every change until the process is stopped, build errors
+// are printed and watching continues
+pub fn watch(mut web: Web, book: bool) -> Result<()> {
+    if let Err(e) = build(&mut web, book) {
+        println!("Error processing files: {}", e);
+    }
+    watch_changes(web, book, || {})
+}
+
+// rebuild `web` whenever its files change, `on_rebuild` is called after
+// each successful build
+pub(crate) fn watch_changes<F: FnMut()>(mut web: Web, book: bool, mut on_rebuild: F) -> Result<()> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error(&web.in_path))?;
+    watcher
+        .watch(&web.in_path, RecursiveMode::Recursive)
+        .map_err(watch_error(&web.in_path))?;
+    watcher
+        .watch(&web.template_dir_path, RecursiveMode::Recursive)
+        .map_err(watch_error(&web.template_dir_path))?;
+    // notify reports absolute paths
+    let abs_out = web.out_path.absolute();
+    let abs_templates = web.template_dir_path.absolute();
+    // a configured data directory can be outside the source directory
+    let data_dir = web.data_dir();
+    let abs_data = data_dir.absolute();
+    let watch_data = data_dir.is_dir() && !abs_data.starts_with(web.in_path.absolute());
+    if watch_data {
+        watcher
+            .watch(&data_dir, RecursiveMode::Recursive)
+            .map_err(watch_error(&data_dir))?;
+    }
+    println!(
+        "watching {} and {}{} for changes (ctrl-c to stop)",
+        web.in_path.display(),
+        web.template_dir_path.display(),
+        if watch_data {
+            format!(" and {}", data_dir.display())
+        } else {
+            String::new()
+        }
+    );
+
+    loop {
+        // block until something happens, then collect events until quiet
+        let mut changes = Changes::default();
+        let mut next = rx.recv().map_err(|_| RecvTimeoutError::Disconnected);
+        loop {
+            match next {
+                Ok(Ok(event)) => {
+                    if !matches!(event.kind, EventKind::Access(_)) {
+                        for path in event.paths.iter().filter(|p| !p.starts_with(&abs_out)) {
+                            info!("changed: {}", path.display());
+                            // data files are reloaded with the documents
+                            if path.starts_with(&abs_templates) && !path.starts_with(&abs_data) {
+                                changes.templates = true;
+                            } else {
+                                changes.sources = true;
+                            }
+                        }
+                    }
+                }
+                Ok(Err(e)) => println!("watch error: {}", e),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+            next = rx.recv_timeout(DEBOUNCE);
+        }
+        if !changes.sources && !changes.templates {
+            continue;
+        }
+
+        println!("change detected, rebuilding...");
+        let result = (|| {
+            if changes.templates {
+                web.reload_templates()?;
+            }
+            if changes.sources {
+                web.reload_documents()?;
+            }
+            build(&mut web, book)
+        })();
+        match result {
+            Ok(_) => on_rebuild(),
+            Err(e) => println!("Error processing files: {}", e),
+        }
+    }
+}
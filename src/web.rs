@@ -1,13 +1,28 @@
-use crate::document::Document;
+use crate::config::Config;
+use crate::data;
+use crate::document::{Document, Format, FrontMatter};
+use crate::error::{Error, IoResultExt, Result};
+use crate::exclude::Excludes;
+use crate::helpers;
+use crate::manifest::{self, FileRecord, Manifest, SourceRecord};
+use crate::nav::{NavNode, NavPage};
+use crate::permalink::{self, Permalink};
+use crate::plan::{Action, BuildPlan, PlanEntry};
+use crate::report::{BuildReport, DocumentReport, Warning};
+use crate::schema::Schema;
+use crate::section::{self, SECTION_TEMPLATE};
 use crate::util::*;
-use anyhow::Context;
 use epub_builder::{EpubBuilder, ZipLibrary};
 use handlebars::Handlebars;
+use rayon::prelude::*;
 use rust_embed::RustEmbed;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::ffi::OsStr;
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use walkdir::WalkDir;
 
 pub struct Web<'a> {
@@ -15,7 +30,104 @@ pub struct Web<'a> {
     pub out_path: PathBuf,
     pub template_dir_path: PathBuf,
     doc_list: Vec<Document>,
+    // index into `doc_list` by normalized source path, for resolving links
+    doc_index: HashMap<PathBuf, usize>,
     pub template_registry: Handlebars<'a>,
+    // template name to the layout it's rendered into, from the
+    // `{{!-- layout: base --}}` declarations
+    layouts: BTreeMap<String, String>,
+    pub config: Config,
+    // `data` template variable, from the files in the data directory
+    data: serde_json::Value,
+    data_hash: String,
+    // `site` template variable, for the build in progress
+    site: serde_json::Value,
+    // pages of the website being built, for `nav` and `breadcrumbs`
+    nav: Option<NavNode>,
+    // from `config`, parsed when the site is loaded
+    permalink: Permalink,
+    // number of threads for generating the website, 0 is one per cpu
+    pub jobs: usize,
+    // skip output directory checks and delete everything in it when cleaning
+    // (unless it holds the source or template directory)
+    pub force: bool,
+    // include documents with front matter `draft: true`
+    pub drafts: bool,
+    // include documents with a `publish_date` in the future
+    pub future: bool,
+    // write a json build report to this file after every build
+    pub report_path: Option<PathBuf>,
+    // collected while generating, for the build report
+    warnings: Mutex<Vec<Warning>>,
+}
+
+// epub_builder errors only implement Debug
+fn epub_error<E: std::fmt::Debug>(action: &'static str) -> impl Fn(E) -> Error {
+    move |err| Error::Epub(format!("{}: {:?}", action, err))
+}
+
+const EPUB_FILE_NAME: &str = "book.epub"; // TODO: use outpath or add book output path?
+
+// front matter defaults for every document in a directory and below it
+const DEFAULTS_FILE_NAME: &str = "_defaults.yaml";
+
+fn file_size<P: AsRef<Path>>(path: P) -> Option<u64> {
+    fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+// how templates changed since the previous website build
+struct TemplateChanges {
+    records: BTreeMap<String, FileRecord>,
+    changed: BTreeSet<String>,
+    rebuild_all: bool,
+    // templates that can see the page list (`site.pages`, `nav` and
+    // `breadcrumbs`), and a hash of it for the pages rendered with them
+    page_list_templates: BTreeSet<String>,
+    pages_hash: String,
+    // templates that can see the `data` variable
+    data_templates: BTreeSet<String>,
+}
+
+// what a document is in the epub, based on its file name
+enum BookRole {
+    Cover,
+    TitlePage,
+    Resource,
+    Chapter,
+}
+
+// template variables of a page: its own (like its front matter) and the
+// ones every page gets, which win over its own, borrowed so they are only
+// copied into the handlebars context
+#[derive(Serialize)]
+pub(crate) struct PageVars<'a> {
+    #[serde(flatten)]
+    pub own: Option<&'a serde_json::Map<String, serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<&'a str>,
+    root: String,
+    page_url: &'a str,
+    base_url: String,
+    site: &'a serde_json::Value,
+    data: &'a serde_json::Value,
+    nav: serde_json::Value,
+    breadcrumbs: serde_json::Value,
+}
+
+impl PageVars<'_> {
+    // names of the variables every page gets
+    pub const NAMES: [&'static str; 8] = [
+        "body",
+        "root",
+        "page_url",
+        "base_url",
+        "site",
+        "data",
+        "nav",
+        "breadcrumbs",
+    ];
 }
 
 #[derive(RustEmbed)]
@@ -23,57 +135,148 @@ pub struct Web<'a> {
 #[exclude = ".*"] // ignore hidden files
 struct Asset;
 
+// the data directory, `_data` in the source directory unless configured
+fn data_dir(in_path: &Path, config: &Config) -> PathBuf {
+    in_path.join(
+        config
+            .data_dir
+            .as_deref()
+            .unwrap_or(Path::new(data::DEFAULT_DATA_DIR)),
+    )
+}
+
+// config patterns, and the data directory isn't part of the site either
+fn source_excludes(in_path: &Path, config: &Config) -> Result<Excludes> {
+    let mut patterns = config.exclude.clone();
+    if let Some(data_dir) = &config.data_dir {
+        patterns.push(format!("/{}/", permalink::path_url(data_dir)));
+    }
+    Excludes::new(in_path, &patterns)
+}
+
 // this is a weird plance for this function
 // TODO: consider refactoring once book/website feel done
-fn new_doc_list<P: AsRef<Path>>(path_ref: P) -> anyhow::Result<Vec<Document>> {
+// documents in `path_ref`, the front matter of all of them is read and
+// checked against `schemas` before any errors are returned
+fn new_doc_list<P: AsRef<Path>>(
+    path_ref: P,
+    excludes: &Excludes,
+    schemas: &[Schema],
+) -> Result<Vec<Document>> {
     let mut vec: Vec<Document> = Vec::new();
     let root = path_ref.as_ref().to_path_buf();
+    let mut errors = Vec::new();
+    // defaults for each directory, merged with its parents' (directories
+    // are visited before what's in them)
+    let mut defaults: HashMap<PathBuf, FrontMatter> = HashMap::new();
 
-    let walker = WalkDir::new(root).follow_links(true).into_iter();
-    for entry_result in walker.filter_entry(|e| !e.is_hidden()) {
+    let walker = WalkDir::new(&root)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter();
+    for entry_result in walker.filter_entry(|e| excludes.includes(e)) {
         let entry = entry_result?;
         let path = entry.path();
-        if fs::metadata(path)?.is_file() {
-            let doc = Document::new(path)
-                .with_context(|| format!("Failed to read {}", path.display()))?;
+        let metadata = fs::metadata(path).with_path(path)?;
+        if metadata.is_dir() {
+            let mut dir_defaults = path
+                .parent()
+                .and_then(|parent| defaults.get(parent))
+                .cloned()
+                .unwrap_or_default();
+            let defaults_path = path.join(DEFAULTS_FILE_NAME);
+            if defaults_path.is_file() {
+                let text = fs::read_to_string(&defaults_path).with_path(&defaults_path)?;
+                match FrontMatter::parse_defaults(&text, &defaults_path) {
+                    Ok(own) => dir_defaults = own.with_defaults(&dir_defaults),
+                    Err(e) => errors.push(e),
+                }
+            }
+            defaults.insert(path.to_path_buf(), dir_defaults);
+        } else if metadata.is_file() {
+            if path.file_name() == Some(OsStr::new(DEFAULTS_FILE_NAME)) {
+                continue;
+            }
+            let mut doc = match Document::new(path) {
+                Ok(doc) => doc,
+                Err(e @ Error::FrontMatter { .. }) => {
+                    errors.push(e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some(dir_defaults) = path.parent().and_then(|dir| defaults.get(dir)) {
+                doc.apply_defaults(dir_defaults);
+            }
+            if doc.is_markdown() {
+                let rel_path = path.strip_prefix(&root).expect("strip prefix match");
+                for schema in schemas {
+                    if schema.applies_to(rel_path, doc.template_name()) {
+                        errors.extend(schema.check(&doc));
+                    }
+                }
+            }
             vec.push(doc);
         }
     }
-    Ok(vec)
+    match errors.len() {
+        0 => Ok(vec),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Validation(errors)),
+    }
+}
+
+fn doc_index(doc_list: &[Document]) -> HashMap<PathBuf, usize> {
+    doc_list
+        .iter()
+        .enumerate()
+        .map(|(index, doc)| (permalink::normalize_path(&doc.source_path), index))
+        .collect()
 }
 
 impl Web<'_> {
     // copy embedded templates into given directory path
-    fn inflate_default_templates<P: AsRef<Path>>(templatedir_path: P) -> anyhow::Result<()> {
+    pub(crate) fn inflate_default_templates<P: AsRef<Path>>(templatedir_path: P) -> Result<()> {
         info!("inflating default templates");
         for relative_path_str in Asset::iter() {
             info!("  {}", relative_path_str);
             let relative_path = PathBuf::from(relative_path_str.to_string());
             let new_template_path = Path::new("").join(&templatedir_path).join(&relative_path);
-            new_template_path.create_all_parent_dir()?;
+            new_template_path
+                .create_all_parent_dir()
+                .with_path(&new_template_path)?;
             let mut file = std::fs::OpenOptions::new()
                 .write(true)
                 .create(true)
                 .open(&new_template_path)
                 .unwrap();
-            file.write_all(&Asset::get(&relative_path_str).unwrap().data.as_ref())?;
+            file.write_all(&Asset::get(&relative_path_str).unwrap().data.as_ref())
+                .with_path(&new_template_path)?;
         }
         Ok(())
     }
 
     // copy files recursively from `source_dir` to `dest_dir`
-    // omitting files with extension `omit_ext` (.gitignore syntax from globwalk crate)
+    // omitting files with extension `omit_ext` and `excludes`
+    // files with the same content as in the `previous` build are not copied again,
+    // every file is recorded in `copied`
+    // directories are created first, then files are copied on `pool`
     fn copy_files<P: AsRef<Path>>(
+        pool: &rayon::ThreadPool,
         source_dir: P,
         dest_dir: P,
         omit_ext: &str,
-    ) -> anyhow::Result<()> {
+        excludes: &Excludes,
+        previous: &BTreeMap<String, FileRecord>,
+        copied: &mut BTreeMap<String, FileRecord>,
+    ) -> Result<()> {
         info!("copyfiles, omitting {}", omit_ext);
         info!(" std::env::current_dir: {:?}", std::env::current_dir());
+        let mut jobs: Vec<(String, PathBuf, PathBuf)> = Vec::new();
         let walker = WalkDir::new(&source_dir).follow_links(true).into_iter();
-        for entry_result in walker
-            .filter_entry(|e| !e.is_hidden() && e.path().extension() != Some(OsStr::new(omit_ext)))
-        {
+        for entry_result in walker.filter_entry(|e| {
+            excludes.includes(e) && e.path().extension() != Some(OsStr::new(omit_ext))
+        }) {
             if let Ok(dir_entry) = entry_result {
                 let rel_path = dir_entry
                     .path()
@@ -83,38 +286,57 @@ impl Web<'_> {
                 let dest_path = PathBuf::from(dest_dir.as_ref()).join(rel_path);
                 if dir_entry.path().is_dir() {
                     info!("  dir: {:?}", dest_path);
-                    fs::create_dir_all(&dest_path)?;
+                    fs::create_dir_all(&dest_path).with_path(&dest_path)?;
                 } else {
-                    // copy file
                     info!("  file: {:?}", dir_entry.path());
-
-                    match fs::copy(dir_entry.path(), &dest_path) {
-                        Ok(bytes) => info!(
-                            "copy {} bytes-> {}\t{}",
-                            bytes,
-                            dir_entry.path().display(),
-                            &dest_path.display()
-                        ),
-                        Err(e) => anyhow::bail!(
-                            "error: {}, failed to copy from: {} to {}",
-                            e,
-                            dir_entry.path().display(),
-                            &dest_path.display()
-                        ),
-                    }
+                    jobs.push((
+                        manifest::path_key(rel_path),
+                        dir_entry.into_path(),
+                        dest_path,
+                    ));
                 }
             }
         }
+
+        // in walk order, so the first error is the same whichever thread
+        // finishes first
+        let results: Vec<Result<FileRecord>> = pool.install(|| {
+            jobs.par_iter()
+                .map(|(key, source_path, dest_path)| {
+                    let record =
+                        FileRecord::new(source_path, previous.get(key)).with_path(source_path)?;
+                    if record.same_content(previous.get(key)) && dest_path.exists() {
+                        info!("unchanged {}", dest_path.display());
+                        return Ok(record);
+                    }
+                    // copying a file onto itself would truncate it
+                    if source_path.is_same_file(dest_path) {
+                        return Ok(record);
+                    }
+                    let bytes = fs::copy(source_path, dest_path).with_path(dest_path)?;
+                    info!(
+                        "copy {} bytes-> {}\t{}",
+                        bytes,
+                        source_path.display(),
+                        dest_path.display()
+                    );
+                    Ok(record)
+                })
+                .collect()
+        });
+        for ((key, _, _), result) in jobs.into_iter().zip(results) {
+            copied.insert(key, result?);
+        }
         Ok(())
     }
 
-    fn path_not_found<P: AsRef<Path>>(path: P) -> anyhow::Result<bool> {
+    fn path_not_found<P: AsRef<Path>>(path: P) -> Result<bool> {
         if let Err(err) = fs::metadata(&path) {
             match err.kind() {
                 std::io::ErrorKind::NotFound => return Ok(true),
                 _ => {
                     error!("Error finding templates directory"); // TODO: remove 'templates' or take param?
-                    return Err(err.into());
+                    return Err(Error::io(path, err));
                 }
             }
         }
@@ -122,43 +344,431 @@ impl Web<'_> {
     }
 
     // creates required folders (but does not delete any old files)
-    pub fn new<P: AsRef<Path>>(
+    pub fn new<P: AsRef<Path>>(in_path: P, out_path: P, templatedir_path: P) -> Result<Self> {
+        Self::with_config(in_path, out_path, templatedir_path, Config::default())
+    }
+
+    pub fn with_config<P: AsRef<Path>>(
         in_path: P,
         out_path: P,
         templatedir_path: P,
-    ) -> anyhow::Result<Self> {
-        fs::create_dir_all(&in_path)?;
+        config: Config,
+    ) -> Result<Self> {
+        fs::create_dir_all(&in_path).with_path(&in_path)?;
         // create templates directory and fill with default templates if needed
         if Self::path_not_found(&templatedir_path)? {
-            fs::create_dir_all(&templatedir_path)?;
+            fs::create_dir_all(&templatedir_path).with_path(&templatedir_path)?;
             Self::inflate_default_templates(&templatedir_path)?;
         }
+        Self::read_only(in_path, out_path, templatedir_path, config)
+    }
 
-        let mut handlebars = Handlebars::new();
-        handlebars.register_templates_directory(".hbs", &templatedir_path)?;
-        handlebars.register_escape_fn(handlebars::no_escape);
+    // like `with_config`, but doesn't create anything, for planning a build:
+    // a missing source directory has no documents and without a templates
+    // directory the default templates are used
+    pub fn read_only<P: AsRef<Path>>(
+        in_path: P,
+        out_path: P,
+        templatedir_path: P,
+        config: Config,
+    ) -> Result<Self> {
+        let permalink = match &config.permalink {
+            Some(permalink) => permalink.parse()?,
+            None => Permalink::default(),
+        };
+        let doc_list = match in_path.as_ref().is_dir() {
+            true => new_doc_list(
+                &in_path,
+                &source_excludes(in_path.as_ref(), &config)?,
+                &config.schemas,
+            )?,
+            false => Vec::new(),
+        };
+        let (data, data_hash) = data::load(data_dir(in_path.as_ref(), &config))?;
+        let template_registry = Self::new_template_registry(&templatedir_path)?;
+        let layouts = Self::template_layouts(
+            &templatedir_path,
+            &Excludes::new(&templatedir_path, &config.exclude)?,
+            &template_registry,
+        )?;
         Ok(Web {
             in_path: in_path.as_ref().to_path_buf(),
             out_path: out_path.as_ref().to_path_buf(),
             template_dir_path: templatedir_path.as_ref().to_path_buf(),
-            doc_list: new_doc_list(in_path)?,
-            template_registry: handlebars,
+            doc_index: doc_index(&doc_list),
+            doc_list,
+            template_registry,
+            layouts,
+            config,
+            data,
+            data_hash,
+            site: serde_json::Value::Null,
+            nav: None,
+            permalink,
+            jobs: 0,
+            force: false,
+            drafts: false,
+            future: false,
+            report_path: None,
+            warnings: Mutex::new(Vec::new()),
         })
     }
 
+    fn new_template_registry<'reg, P: AsRef<Path>>(
+        templatedir_path: P,
+    ) -> Result<Handlebars<'reg>> {
+        let mut handlebars = Handlebars::new();
+        if templatedir_path.as_ref().is_dir() {
+            handlebars.register_templates_directory(".hbs", &templatedir_path)?;
+        } else {
+            // what `inflate_default_templates` would write
+            for file_name in Asset::iter() {
+                if let Some(name) = file_name.strip_suffix(".hbs") {
+                    let file = Asset::get(&file_name).expect("embedded template");
+                    let text = String::from_utf8_lossy(file.data.as_ref()).into_owned();
+                    handlebars.register_template_string(name, text)?;
+                }
+            }
+        }
+        // sites created before there were section pages don't have one
+        if !handlebars.has_template(SECTION_TEMPLATE) {
+            let file = Asset::get("section.hbs").expect("embedded section template");
+            let text = String::from_utf8_lossy(file.data.as_ref()).into_owned();
+            handlebars.register_template_string(SECTION_TEMPLATE, text)?;
+        }
+        handlebars.register_escape_fn(handlebars::no_escape);
+        helpers::register(&mut handlebars);
+        Ok(handlebars)
+    }
+
+    // layout declarations of the templates in `templatedir_path`, every
+    // layout must be a registered template and a layout can't contain itself
+    fn template_layouts<P: AsRef<Path>>(
+        templatedir_path: P,
+        excludes: &Excludes,
+        registry: &Handlebars,
+    ) -> Result<BTreeMap<String, String>> {
+        let templatedir_path = templatedir_path.as_ref();
+        let mut layouts = BTreeMap::new();
+        if !templatedir_path.is_dir() {
+            return Ok(layouts);
+        }
+        let walker = WalkDir::new(templatedir_path)
+            .follow_links(true)
+            .into_iter();
+        for entry_result in walker.filter_entry(|e| excludes.includes(e)) {
+            let dir_entry = entry_result?;
+            if dir_entry.file_type().is_file()
+                && dir_entry.path().extension() == Some(OsStr::new("hbs"))
+            {
+                let text = fs::read_to_string(dir_entry.path()).with_path(dir_entry.path())?;
+                if let Some(layout) = layout_declaration(&text) {
+                    let rel_path = dir_entry
+                        .path()
+                        .strip_prefix(templatedir_path)
+                        .expect("strip prefix match")
+                        .with_extension("");
+                    layouts.insert(manifest::path_key(rel_path), layout.to_string());
+                }
+            }
+        }
+        for (name, layout) in layouts.iter() {
+            if !registry.has_template(layout) {
+                return Err(Error::Template {
+                    name: Some(name.clone()),
+                    line: Some(1),
+                    message: format!("layout '{}' not found", layout),
+                });
+            }
+            let mut chain = vec![name.as_str()];
+            while let Some(layout) = layouts.get(*chain.last().unwrap()) {
+                if chain.contains(&layout.as_str()) {
+                    chain.push(layout);
+                    return Err(Error::Template {
+                        name: Some(name.clone()),
+                        line: Some(1),
+                        message: format!("layout cycle {}", chain.join(" -> ")),
+                    });
+                }
+                chain.push(layout);
+            }
+        }
+        Ok(layouts)
+    }
+
+    // `name` and the layouts it's rendered into, innermost first
+    fn layout_chain<'n>(&'n self, name: &'n str) -> Vec<&'n str> {
+        let mut chain = vec![name];
+        while let Some(layout) = self.layouts.get(*chain.last().unwrap()) {
+            chain.push(layout);
+        }
+        chain
+    }
+
+    // render template `name` into its layouts, each layout gets the output of
+    // the template inside it as `body`
+    pub(crate) fn render_page<W: Write>(
+        &self,
+        name: &str,
+        vars: &PageVars,
+        writer: W,
+    ) -> Result<()> {
+        if !self.template_registry.has_template(name) {
+            return Err(Error::Template {
+                name: Some(name.to_string()),
+                line: None,
+                message: "template not found".into(),
+            });
+        }
+        let chain = self.layout_chain(name);
+        let (outermost, inner) = chain.split_last().expect("chain starts with name");
+        // the variables are copied once, every layout uses the same context
+        let mut context = handlebars::Context::wraps(vars)?;
+        for name in inner {
+            let body = self.template_registry.render_with_context(name, &context)?;
+            if let Some(vars) = context.data_mut().as_object_mut() {
+                vars.insert("body".into(), body.into());
+            }
+        }
+        self.template_registry
+            .render_with_context_to_write(outermost, &context, writer)?;
+        Ok(())
+    }
+
+    // keep a warning about `source` for the build report, they are printed
+    // when the build is done, in the same order whichever thread found them
+    pub(crate) fn warn<P: AsRef<Path>>(&self, source: P, message: &str) {
+        self.warnings.lock().unwrap().push(Warning {
+            source: source.as_ref().to_path_buf(),
+            message: message.to_string(),
+        });
+    }
+
+    fn take_warnings(&self) -> Vec<Warning> {
+        std::mem::take(&mut *self.warnings.lock().unwrap())
+    }
+
+    // end the build report and print its (sorted) warnings
+    fn finish_report(&self, report: &mut BuildReport) {
+        report.finish(self.take_warnings());
+        for warning in report.warnings.iter() {
+            println!("warning: {}: {}", warning.source.display(), warning.message);
+        }
+    }
+
+    fn save_report(&self, report: &BuildReport) -> Result<()> {
+        if let Some(path) = &self.report_path {
+            report.save(path).with_path(path)?;
+        }
+        Ok(())
+    }
+
+    // register templates again, after files in template directory changed
+    pub fn reload_templates(&mut self) -> Result<()> {
+        let template_registry = Self::new_template_registry(&self.template_dir_path)?;
+        self.layouts = Self::template_layouts(
+            &self.template_dir_path,
+            &self.template_excludes()?,
+            &template_registry,
+        )?;
+        self.template_registry = template_registry;
+        Ok(())
+    }
+
+    // read source directory again, after files were added, removed or changed
+    pub fn reload_documents(&mut self) -> Result<()> {
+        self.doc_list = new_doc_list(
+            &self.in_path,
+            &self.source_excludes()?,
+            &self.config.schemas,
+        )?;
+        self.doc_index = doc_index(&self.doc_list);
+        (self.data, self.data_hash) = data::load(self.data_dir())?;
+        Ok(())
+    }
+
+    // where the `data` template variable is read from
+    pub(crate) fn data_dir(&self) -> PathBuf {
+        data_dir(&self.in_path, &self.config)
+    }
+
+    // `.webgenrignore` is read again every time, in case it changed
+    fn source_excludes(&self) -> Result<Excludes> {
+        source_excludes(&self.in_path, &self.config)
+    }
+
+    fn template_excludes(&self) -> Result<Excludes> {
+        Excludes::new(&self.template_dir_path, &self.config.exclude)
+    }
+
     // given a `source_path` return corresponding output path
-    fn outpath(&self, doc: &Document) -> std::io::Result<PathBuf> {
+    pub(crate) fn outpath(&self, doc: &Document) -> PathBuf {
+        self.out_path.join(permalink::url_file(&self.url(doc)))
+    }
+
+    // url of the output for `doc`, relative to the site root: front matter
+    // `permalink`, or from the configured style and front matter `slug`
+    pub(crate) fn url(&self, doc: &Document) -> String {
         let rel_path = doc
             .source_path
             .strip_prefix(&self.in_path)
             .expect("strip prefix match");
-        Ok(self.out_path.join(rel_path))
+        if !doc.is_markdown() {
+            return permalink::path_url(rel_path);
+        }
+        match doc.var("permalink") {
+            Some(url) => permalink::explicit_url(url),
+            None => self
+                .permalink
+                .page_url(rel_path, doc.var("slug"), doc.date()),
+        }
+    }
+
+    // url of the file `link` (from the source of `doc`) points to, relative
+    // to the page for `doc` (or its chapter in the book), None if it isn't
+    // a source file
+    // the target's manifest key and url go into `links`, so the page can be
+    // generated again when the url changes, a markdown file that doesn't
+    // exist (yet) with an empty url
+    pub(crate) fn link_url(
+        &self,
+        doc: &Document,
+        link: &str,
+        format: Format,
+        links: &mut BTreeMap<String, String>,
+    ) -> Option<String> {
+        if link.is_empty()
+            || link.contains("://")
+            || link.starts_with("mailto:")
+            || link.starts_with("data:")
+        {
+            return None;
+        }
+        let link = percent_decode(link);
+        let target = match link.strip_prefix('/') {
+            Some(from_root) => self.in_path.join(from_root),
+            None => doc.source_path.parent()?.join(&link),
+        };
+        let target = permalink::normalize_path(&target);
+        let key = target
+            .strip_prefix(permalink::normalize_path(&self.in_path))
+            .ok()
+            .map(manifest::path_key);
+        let target_doc = match self.doc_index.get(&target) {
+            Some(&index) => &self.doc_list[index],
+            None => {
+                if let Some(key) = key.filter(|_| target.is_markdown()) {
+                    links.insert(key, String::new());
+                }
+                return None;
+            }
+        };
+        match format {
+            Format::Web => {
+                let url = self.url(target_doc);
+                if let Some(key) = key {
+                    links.insert(key, url.clone());
+                }
+                Some(permalink::relative_url(&self.url(doc), &url))
+            }
+            Format::Epub => Some(permalink::relative_url(
+                &self.book_path(doc)?,
+                &self.book_path(target_doc)?,
+            )),
+        }
+    }
+
+    // current url of the source file with manifest key `key`, empty if
+    // there is no such file
+    fn key_url(&self, key: &str) -> String {
+        let path = permalink::normalize_path(&self.in_path.join(key));
+        match self.doc_index.get(&path) {
+            Some(&index) => self.url(&self.doc_list[index]),
+            None => String::new(),
+        }
+    }
+
+    // configured `base_url` with a trailing slash, or empty
+    pub(crate) fn base_url(&self) -> String {
+        match &self.config.base_url {
+            Some(url) => format!("{}/", url.trim_end_matches('/')),
+            None => String::new(),
+        }
+    }
+
+    // two documents in the website can't have the same output file
+    fn check_permalinks(&self) -> Result<()> {
+        let mut sources = BTreeMap::new();
+        for doc in self.doc_list.iter() {
+            if self.skip_reason(doc, Format::Web).is_some() {
+                continue;
+            }
+            if let Some(other) = sources.insert(self.outpath(doc), &doc.source_path) {
+                return Err(Error::Config(format!(
+                    "{} and {} are both written to {}",
+                    other.display(),
+                    doc.source_path.display(),
+                    self.outpath(doc).display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // identifies settings that affect every output file: the source and
+    // template directories, the config and the --drafts and --future
+    // flags, a previous build with different settings can't be updated
+    // incrementally
+    fn config_hash(&self) -> String {
+        manifest::hash_bytes(
+            format!(
+                "{}\n{}\n{}\n{} {}",
+                self.in_path.display(),
+                self.template_dir_path.display(),
+                serde_json::to_string(&self.config).unwrap_or_default(),
+                self.drafts,
+                self.future,
+            )
+            .as_bytes(),
+        )
+    }
+
+    // hash every handlebars template, keyed by template name
+    fn template_records(
+        &self,
+        previous: &BTreeMap<String, FileRecord>,
+    ) -> Result<BTreeMap<String, FileRecord>> {
+        let mut records = BTreeMap::new();
+        if !self.template_dir_path.is_dir() {
+            return Ok(records);
+        }
+        let excludes = self.template_excludes()?;
+        let walker = WalkDir::new(&self.template_dir_path)
+            .follow_links(true)
+            .into_iter();
+        for entry_result in walker.filter_entry(|e| excludes.includes(e)) {
+            let dir_entry = entry_result?;
+            if dir_entry.file_type().is_file()
+                && dir_entry.path().extension() == Some(OsStr::new("hbs"))
+            {
+                let rel_path = dir_entry
+                    .path()
+                    .strip_prefix(&self.template_dir_path)
+                    .expect("strip prefix match")
+                    .with_extension("");
+                let name = manifest::path_key(rel_path);
+                let record = FileRecord::new(dir_entry.path(), previous.get(&name))
+                    .with_path(dir_entry.path())?;
+                records.insert(name, record);
+            }
+        }
+        Ok(records)
     }
 
     fn add_template_stylesheet_files(
         &self,
         mut epub: EpubBuilder<ZipLibrary>,
-    ) -> anyhow::Result<EpubBuilder<ZipLibrary>> {
+    ) -> Result<EpubBuilder<ZipLibrary>> {
         info!(
             "add_template_stylesheet_files from {}",
             self.template_dir_path.display()
@@ -167,10 +777,11 @@ impl Web<'_> {
         let walker = WalkDir::new(&self.template_dir_path)
             .follow_links(true)
             .into_iter();
+        let excludes = self.template_excludes()?;
 
-        for entry_result in walker
-            .filter_entry(|e| !e.is_hidden() && e.path().extension() != Some(OsStr::new("hbs")))
-        {
+        for entry_result in walker.filter_entry(|e| {
+            excludes.includes(e) && e.path().extension() != Some(OsStr::new("hbs"))
+        }) {
             let dir_entry = entry_result?;
             if dir_entry.file_type().is_file() {
                 info!("  dir_entry: {:?}", dir_entry.path().display());
@@ -182,49 +793,269 @@ impl Web<'_> {
 
                 let mimetype = rel_path.mimetype().unwrap_or(mime::TEXT_PLAIN_UTF_8);
                 info!("  rel_path: {}, mimetype: {}", rel_path.display(), mimetype);
-                let result = epub.add_resource(
+                epub.add_resource(
                     rel_path,
-                    fs::File::open(dir_entry.path())?,
+                    fs::File::open(dir_entry.path()).with_path(dir_entry.path())?,
                     mimetype.to_string(),
-                );
-                // TODO: figure out why "?" doesn't work at end of statement above
-                if result.is_err() {
-                    anyhow::bail!(
-                        "failed to add resource to epub: {}",
-                        dir_entry.path().display()
-                    )
-                }
+                )
+                .map_err(epub_error("adding stylesheet"))?;
             }
         }
         info!("done");
         Ok(epub)
     }
 
-    fn make_book_internal(&self, author: &str, title: &str) -> anyhow::Result<()> {
-        use anyhow::anyhow;
+    // why `doc` isn't part of the `format` output, if it isn't: front
+    // matter `formats` excludes it, or it's a draft or not published yet
+    fn skip_reason(&self, doc: &Document, format: Format) -> Option<String> {
+        if !doc.includes_format(format) {
+            return Some(format!("not included in {}", format.name()));
+        }
+        self.held_back(doc)
+    }
+
+    fn held_back(&self, doc: &Document) -> Option<String> {
+        doc.held_back(self.drafts, self.future)
+    }
+
+    // template variables every page gets for the page at `page_url` (`nav`
+    // and `breadcrumbs` only on the website), its own are added by the caller
+    pub(crate) fn page_vars<'a>(&'a self, page_url: &'a str) -> PageVars<'a> {
+        let (nav, breadcrumbs) = match &self.nav {
+            Some(nav) => (nav.to_json(page_url), nav.breadcrumbs(page_url).into()),
+            None => Default::default(),
+        };
+        PageVars {
+            own: None,
+            body: None,
+            banner: None,
+            root: permalink::relative_url(page_url, ""),
+            page_url,
+            base_url: self.base_url(),
+            site: &self.site,
+            data: &self.data,
+            nav,
+            breadcrumbs,
+        }
+    }
+
+    // generated section pages are the index pages of their directories
+    fn nav_tree(&self, sections: &[PathBuf]) -> NavNode {
+        let section_indexes: Vec<PathBuf> = sections.iter().map(|dir| dir.join("index")).collect();
+        let pages = self
+            .doc_list
+            .iter()
+            .filter(|doc| doc.is_markdown() && self.skip_reason(doc, Format::Web).is_none())
+            .map(|doc| NavPage {
+                rel_path: doc
+                    .source_path
+                    .strip_prefix(&self.in_path)
+                    .expect("strip prefix match"),
+                title: doc.title(),
+                url: self.url(doc),
+                weight: doc.weight(),
+            });
+        let sections = sections
+            .iter()
+            .zip(section_indexes.iter())
+            .map(|(dir, index)| NavPage {
+                rel_path: index,
+                title: None,
+                url: section::url(dir),
+                weight: None,
+            });
+        NavNode::new(pages.chain(sections))
+    }
+
+    // everything on the website, for section pages
+    fn section_entries(&self) -> Vec<section::Entry<'_>> {
+        self.doc_list
+            .iter()
+            .filter(|doc| self.skip_reason(doc, Format::Web).is_none())
+            .map(|doc| section::Entry {
+                rel_path: doc
+                    .source_path
+                    .strip_prefix(&self.in_path)
+                    .expect("strip prefix match"),
+                url: self.url(doc),
+                is_page: doc.is_markdown(),
+                title: doc.title(),
+                date: doc.date().map(|date| date.format("%Y-%m-%d").to_string()),
+                weight: doc.weight(),
+                size: (!doc.is_markdown())
+                    .then(|| file_size(&doc.source_path))
+                    .flatten(),
+            })
+            .collect()
+    }
+
+    // directories that get a generated section page
+    fn section_dirs(&self) -> Vec<PathBuf> {
+        if !self.config.section_pages {
+            return Vec::new();
+        }
+        section::directories(&self.section_entries())
+    }
+
+    // render the section page for `dir`, only written if it changed,
+    // returns whether it was
+    fn gen_section(
+        &self,
+        dir: &Path,
+        entries: &[section::Entry],
+        sections: &[PathBuf],
+    ) -> Result<bool> {
+        let page_url = section::url(dir);
+        let mut listing = section::listing(dir, entries, sections);
+        let title = match dir.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => self.config.title.clone().unwrap_or_else(|| "Home".into()),
+        };
+        listing.insert("title".into(), title.into());
+        let mut vars = self.page_vars(&page_url);
+        vars.own = Some(&listing);
+        let mut html = Vec::new();
+        self.render_page(SECTION_TEMPLATE, &vars, &mut html)?;
+        let outpath = self.out_path.join(permalink::url_file(&page_url));
+        if fs::read(&outpath).is_ok_and(|previous| previous == html) {
+            return Ok(false);
+        }
+        info!("section-> {}", outpath.display());
+        outpath.create_all_parent_dir().with_path(&outpath)?;
+        fs::write(&outpath, html).with_path(&outpath)?;
+        Ok(true)
+    }
+
+    // the `site` template variable: metadata from the config, every page
+    // and when and with which version the site was built
+    fn site_vars(&self, format: Format) -> serde_json::Value {
+        serde_json::json!({
+            "title": self.config.title,
+            "author": self.config.author,
+            "base_url": self.base_url(),
+            "pages": self.site_pages(format),
+            "build": {
+                "time": chrono::Local::now().to_rfc3339(),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })
+    }
+
+    // markdown documents in `format`, for the website newest first (pages
+    // without a date after the others, in file name order), for the book
+    // in chapter order
+    fn site_pages(&self, format: Format) -> Vec<serde_json::Value> {
+        let mut docs: Vec<&Document> = match format {
+            Format::Web => self.doc_list.iter().collect(),
+            Format::Epub => self.book_docs(),
+        };
+        if format == Format::Web {
+            docs.sort_by_key(|doc| std::cmp::Reverse(doc.date()));
+        }
+        docs.into_iter()
+            .filter(|doc| doc.is_markdown() && self.skip_reason(doc, format).is_none())
+            .filter_map(|doc| {
+                let url = match format {
+                    Format::Web => self.url(doc),
+                    Format::Epub => match self.book_role(doc) {
+                        Ok(BookRole::Chapter) => self.book_path(doc)?,
+                        _ => return None,
+                    },
+                };
+                Some(serde_json::json!({
+                    "url": url,
+                    "title": doc.title(),
+                    "date": doc.date().map(|date| date.format("%Y-%m-%d").to_string()),
+                    "tags": doc.tags(),
+                    "front_matter": doc.front_matter(),
+                }))
+            })
+            .collect()
+    }
+
+    // cover and title page first, then chapters in file name order, unless
+    // front matter has `order: N`
+    fn book_docs(&self) -> Vec<&Document> {
+        let mut docs: Vec<&Document> = self.doc_list.iter().collect();
+        docs.sort_by_key(|doc| {
+            let rank = match self.book_role(doc) {
+                Ok(BookRole::Cover) => 0,
+                Ok(BookRole::TitlePage) => 1,
+                _ => 2,
+            };
+            (rank, doc.order().unwrap_or(i64::MAX))
+        });
+        docs
+    }
+
+    // path of `doc` in the epub, None for the cover image
+    fn book_path(&self, doc: &Document) -> Option<String> {
+        match self.book_role(doc).ok()? {
+            BookRole::Cover => None,
+            BookRole::TitlePage => Some(doc.source_path.file_name()?.to_string_lossy().into()),
+            BookRole::Resource => Some(permalink::path_url(
+                doc.source_path.strip_prefix(&self.in_path).ok()?,
+            )),
+            BookRole::Chapter => Some(format!("{}.xhtml", doc.file_stem().ok()?)),
+        }
+    }
+
+    fn book_role(&self, doc: &Document) -> Result<BookRole> {
+        Ok(match doc.file_stem()? {
+            "cover" | "_cover" => BookRole::Cover,
+            "title" | "_title" => BookRole::TitlePage,
+            _ if !doc.is_markdown() && !Self::is_html(&doc.source_path) => BookRole::Resource,
+            _ => BookRole::Chapter,
+        })
+    }
+
+    fn make_book_internal(
+        &self,
+        author: &str,
+        title: &str,
+        report: &mut BuildReport,
+    ) -> Result<()> {
         use epub_builder::EpubContent;
         use epub_builder::ReferenceType;
         use std::fs::File;
 
-        let epub_filename = "book.epub"; // TODO: use outpath or add book output path?
-        let writer = std::fs::File::create(epub_filename)?;
-        let zip_lib = ZipLibrary::new().map_err(|err| anyhow!("initializing zip {:#?}", err))?;
-        let mut epub =
-            EpubBuilder::new(zip_lib).map_err(|err| anyhow!("initializing epub {:#?}", err))?;
+        let epub_filename = EPUB_FILE_NAME;
+        let writer = std::fs::File::create(epub_filename).with_path(epub_filename)?;
+        let zip_lib = ZipLibrary::new().map_err(epub_error("initializing zip"))?;
+        let mut epub = EpubBuilder::new(zip_lib).map_err(epub_error("initializing epub"))?;
 
-        epub = self
-            .add_template_stylesheet_files(epub)
-            .map_err(|err| anyhow!("adding epub stylesheets {:#?}", err))?;
+        report.phase("stylesheets");
+        epub = self.add_template_stylesheet_files(epub)?;
 
         epub.add_author(author);
         epub.set_title(title);
         let mut chapter_number = 1;
 
-        for doc in &self.doc_list {
+        report.phase("documents");
+        for doc in self.book_docs() {
+            let mut entry = DocumentReport {
+                source: doc.source_path.clone(),
+                output: None,
+                action: Action::Copy,
+                reason: None,
+                size: file_size(&doc.source_path),
+            };
+            if let Some(reason) = self.skip_reason(doc, Format::Epub) {
+                info!("skipping {}, {}", doc.source_path.display(), reason);
+                let held_back = self.held_back(doc).is_some();
+                entry.action = Action::Skip;
+                entry.reason = Some(reason);
+                entry.size = None;
+                report.add_document(entry);
+                if held_back {
+                    report.held_back.push(doc.source_path.clone());
+                }
+                continue;
+            }
             let file_stem = doc.file_stem()?;
 
-            match file_stem {
-                "cover" | "_cover" => {
+            match self.book_role(doc)? {
+                BookRole::Cover => {
                     println!("cover: {}", doc.source_path.display());
                     let default_extension = "png";
                     let extension = match doc.source_path.extension() {
@@ -232,25 +1063,30 @@ impl Web<'_> {
                             Some(str) => str,
                             None => {
                                 // this should never happen, making it an error
-                                anyhow::bail!(
+                                return Err(Error::Epub(format!(
                                     "unexpected cover image file extension '{:?}'",
                                     os_str
-                                );
+                                )));
                             }
                         },
                         None => {
-                            println!("no file extension for cover image, assuming png");
+                            self.warn(
+                                &doc.source_path,
+                                "no file extension for cover image, assuming png",
+                            );
                             default_extension
                         }
                     };
                     epub.add_cover_image(
                         &doc.source_path,
-                        File::open(&doc.source_path)?,
+                        File::open(&doc.source_path).with_path(&doc.source_path)?,
                         format!("image/{}", extension),
                     )
-                    .map_err(|err| anyhow!("adding cover image {:#?}", err))?;
+                    .map_err(epub_error("adding cover image"))?;
+                    entry.output = Some(doc.source_path.clone());
+                    entry.reason = Some("cover image".into());
                 }
-                "title" | "_title" => {
+                BookRole::TitlePage => {
                     println!("title page: {}", doc.source_path.display());
                     let file_name = doc.source_path.file_name().unwrap().to_string_lossy();
                     if doc.is_markdown() {
@@ -259,25 +1095,54 @@ impl Web<'_> {
                             doc.source_path.display(),
                             file_name
                         );
-                        // TODO: refactor webgen to create a fn that returns impl Read something
-                        let s: String = doc.gen_html(&self)?;
+                        let mut html = Vec::new();
+                        doc.write_page(self, Format::Epub, &mut html)
+                            .map_err(|e| Error::document(&doc.source_path, e))?;
+                        entry.action = Action::Render;
+                        entry.size = Some(html.len() as u64);
                         epub.add_content(
-                            EpubContent::new(file_name, s.as_bytes())
+                            EpubContent::new(file_name.as_ref(), html.as_slice())
                                 .title("Title Page")
                                 .reftype(ReferenceType::TitlePage),
                         )
-                        .map_err(|err| anyhow!("adding title page to epub {:#?}", err))?;
+                        .map_err(epub_error("adding title page to epub"))?;
                     } else {
                         epub.add_content(
-                            EpubContent::new(file_name, File::open(&doc.source_path)?)
-                                .title("Title Page")
-                                .reftype(ReferenceType::TitlePage),
+                            EpubContent::new(
+                                file_name.as_ref(),
+                                File::open(&doc.source_path).with_path(&doc.source_path)?,
+                            )
+                            .title("Title Page")
+                            .reftype(ReferenceType::TitlePage),
                         )
-                        .map_err(|err| anyhow!("adding title page to epub {:#?}", err))?;
+                        .map_err(epub_error("adding title page to epub"))?;
                     }
+                    entry.output = Some(PathBuf::from(file_name.as_ref()));
+                    entry.reason = Some("title page".into());
                 }
-                _ => {
-                    let chapter_title = format!("Chapter {}", chapter_number); // TODO: get from YAML front matter
+                BookRole::Resource => {
+                    // images and other media referenced by chapters
+                    let rel_path = doc
+                        .source_path
+                        .strip_prefix(&self.in_path)
+                        .expect("strip prefix match");
+                    let mimetype = rel_path
+                        .mimetype()
+                        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+                    info!("resource: {}, mimetype: {}", rel_path.display(), mimetype);
+                    epub.add_resource(
+                        rel_path,
+                        File::open(&doc.source_path).with_path(&doc.source_path)?,
+                        mimetype.to_string(),
+                    )
+                    .map_err(epub_error("adding resource to epub"))?;
+                    entry.output = Some(rel_path.to_path_buf());
+                }
+                BookRole::Chapter => {
+                    let chapter_title = match doc.title() {
+                        Some(title) => title.to_string(),
+                        None => format!("Chapter {}", chapter_number),
+                    };
                     let zip_path = format!("{}.xhtml", file_stem);
                     if doc.is_markdown() {
                         println!(
@@ -286,15 +1151,17 @@ impl Web<'_> {
                             zip_path,
                             chapter_title
                         );
-
-                        // TODO: refactor webgen to create a fn that returns impl Read something
-                        let s: String = doc.gen_html(&self)?;
+                        let mut html = Vec::new();
+                        doc.write_page(self, Format::Epub, &mut html)
+                            .map_err(|e| Error::document(&doc.source_path, e))?;
+                        entry.action = Action::Render;
+                        entry.size = Some(html.len() as u64);
                         epub.add_content(
-                            EpubContent::new(zip_path, s.as_bytes())
+                            EpubContent::new(zip_path.as_str(), html.as_slice())
                                 .title(chapter_title)
                                 .reftype(ReferenceType::Text),
                         )
-                        .map_err(|err| anyhow!("adding content to epub {:#?}", err))?;
+                        .map_err(epub_error("adding content to epub"))?;
                     } else {
                         println!(
                             "adding {}\tas {},\ttitle: {}",
@@ -303,76 +1170,640 @@ impl Web<'_> {
                             chapter_title
                         );
                         epub.add_content(
-                            EpubContent::new(zip_path, File::open(&doc.source_path)?)
-                                .title(chapter_title)
-                                .reftype(ReferenceType::Text),
+                            EpubContent::new(
+                                zip_path.as_str(),
+                                File::open(&doc.source_path).with_path(&doc.source_path)?,
+                            )
+                            .title(chapter_title)
+                            .reftype(ReferenceType::Text),
                         )
-                        .map_err(|err| anyhow!("adding content to epub {:#?}", err))?;
+                        .map_err(epub_error("adding content to epub"))?;
                     };
 
+                    entry.output = Some(PathBuf::from(zip_path));
                     chapter_number = chapter_number + 1;
                 }
-            } // match file_stem
+            } // match book_role
+            report.add_document(entry);
         }
+        report.phase("generate");
         epub.generate(writer)
-            .map_err(|err| anyhow!("generating epub {:#?}", err))?;
+            .map_err(epub_error("generating epub"))?;
 
         info!("book created: {}", epub_filename);
         Ok(())
     }
 
-    // if folder exists, delete it & all contents and create new
-    fn clean_folder<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
-        if Path::new(path.as_ref()).exists() {
-            fs::remove_dir_all(&path)?;
+    fn is_html(path: &Path) -> bool {
+        match path.mimetype() {
+            Some(mimetype) => {
+                mimetype == mime::TEXT_HTML || mimetype.essence_str() == "application/xhtml+xml"
+            }
+            None => false,
         }
-        fs::create_dir_all(&path)?;
-        Ok(())
     }
 
-    fn source_directory_has_files(&self) -> anyhow::Result<usize> {
+    // refuse to generate into (or clean) a directory that holds the
+    // source or template files, unless `force` is set
+    fn check_out_path(&self) -> Result<()> {
+        match self.out_path_conflict() {
+            Some((name, path)) if !self.force => Err(Error::Config(format!(
+                "output directory {} contains the {} directory {}, use --force to use it anyway",
+                self.out_path.display(),
+                name,
+                path.display()
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    // the source or template directory, if it is inside the output directory
+    fn out_path_conflict(&self) -> Option<(&'static str, &Path)> {
+        let out_path = self.out_path.absolute();
+        [
+            ("source", self.in_path.as_path()),
+            ("template", self.template_dir_path.as_path()),
+        ]
+        .into_iter()
+        .find(|(_, path)| path.absolute().starts_with(&out_path))
+    }
+
+    // delete what previous builds generated in the output directory,
+    // files that aren't in the build manifest are left alone, unless
+    // `force` is set, then the whole directory is removed (never when it
+    // holds the source or template files)
+    // returns: deleted (or with `dry_run`, to be deleted) file paths
+    pub fn clean(&self, dry_run: bool) -> Result<Vec<PathBuf>> {
+        if !self.force {
+            self.check_out_path()?;
+            return self.remove_outputs(dry_run);
+        }
+        if let Some((name, path)) = self.out_path_conflict() {
+            return Err(Error::Config(format!(
+                "not removing output directory {}, it contains the {} directory {}",
+                self.out_path.display(),
+                name,
+                path.display()
+            )));
+        }
+        if !self.out_path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut files = Vec::new();
+        for entry in WalkDir::new(&self.out_path) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                files.push(entry.into_path());
+            }
+        }
+        if !dry_run {
+            fs::remove_dir_all(&self.out_path).with_path(&self.out_path)?;
+        }
+        Ok(files)
+    }
+
+    // delete the files listed in the build manifest and the manifest itself
+    fn remove_outputs(&self, dry_run: bool) -> Result<Vec<PathBuf>> {
+        let manifest = match Manifest::read(&self.out_path) {
+            Some(manifest) => manifest,
+            None => return Ok(Vec::new()),
+        };
+        let mut files = Vec::new();
+        for output in manifest.outputs() {
+            let path = self.out_path.join(output);
+            let exists = path.is_file();
+            if !dry_run {
+                manifest::remove_output(&self.out_path, output).with_path(&path)?;
+            }
+            if exists {
+                files.push(path);
+            }
+        }
+        let manifest_path = self.out_path.join(manifest::MANIFEST_FILE_NAME);
+        if !dry_run {
+            fs::remove_file(&manifest_path).with_path(&manifest_path)?;
+        }
+        files.push(manifest_path);
+        Ok(files)
+    }
+
+    fn source_directory_has_files(&self) -> Result<usize> {
         let num_files = self.doc_list.len();
         if num_files == 0 {
-            anyhow::bail!(
+            return Err(Error::Config(format!(
                 "please add files to source directory: {}",
                 self.in_path.display()
-            )
+            )));
         }
         Ok(num_files)
     }
-    pub fn gen_book(&mut self) -> anyhow::Result<usize> {
+    pub fn gen_book(&mut self) -> Result<usize> {
+        self.build_book()?;
+        Ok(self.doc_list.len())
+    }
+
+    // like `gen_book`, returns the build report
+    pub fn build_book(&mut self) -> Result<BuildReport> {
         self.source_directory_has_files()?;
         info!("generating ePub for {} files", self.doc_list.len());
 
-        match self.make_book_internal("Author Name", "My Book") {
-            Err(e) => anyhow::bail!("Problem creating ebook: {}", e),
-            Ok(_) => Ok(self.doc_list.len()),
+        let mut report = BuildReport::new(Format::Epub.name(), EPUB_FILE_NAME);
+        self.take_warnings();
+        self.site = self.site_vars(Format::Epub);
+        self.nav = None;
+        let author = self.config.author.as_deref().unwrap_or("Author Name");
+        let title = self.config.title.as_deref().unwrap_or("My Book");
+        self.make_book_internal(author, title, &mut report)?;
+        self.finish_report(&mut report);
+        self.save_report(&report)?;
+        Ok(report)
+    }
+
+    // a changed template invalidates the pages that use it or render into
+    // it, if the template isn't used that way it may be a partial, so
+    // rebuild all
+    fn template_changes(&self, previous: &Manifest) -> Result<TemplateChanges> {
+        let records = self.template_records(&previous.templates)?;
+        let mut changed: BTreeSet<String> = BTreeSet::new();
+        for (name, record) in records.iter() {
+            if !record.same_content(previous.templates.get(name)) {
+                changed.insert(name.clone());
+            }
+        }
+        for name in previous.templates.keys() {
+            if !records.contains_key(name) {
+                changed.insert(name.clone());
+            }
         }
+        let mut used_templates: BTreeSet<&str> = self
+            .doc_list
+            .iter()
+            .filter_map(|doc| doc.template_name())
+            .flat_map(|name| self.layout_chain(name))
+            .collect();
+        if self.config.section_pages {
+            used_templates.extend(self.layout_chain(SECTION_TEMPLATE));
+        }
+        let rebuild_all = changed
+            .iter()
+            .any(|name| !used_templates.contains(name.as_str()));
+        let pages = serde_json::json!([self.site_pages(Format::Web), self.section_dirs()]);
+        Ok(TemplateChanges {
+            records,
+            changed,
+            rebuild_all,
+            page_list_templates: self.templates_using(&["site", "nav", "breadcrumbs"])?,
+            pages_hash: manifest::hash_bytes(pages.to_string().as_bytes()),
+            data_templates: self.templates_using(&["data"])?,
+        })
     }
 
-    fn gen_website_clean_and_setup_outpath(&self) -> anyhow::Result<()> {
-        Self::clean_folder(&self.out_path)?;
-        Self::copy_files(&self.template_dir_path, &self.out_path, "hbs")?;
-        Ok(())
+    // templates that mention any of the variables `vars`, or might through
+    // a partial or `@root`
+    fn templates_using(&self, vars: &[&str]) -> Result<BTreeSet<String>> {
+        let mut names = BTreeSet::new();
+        if !self.template_dir_path.is_dir() {
+            return Ok(names);
+        }
+        let excludes = self.template_excludes()?;
+        let walker = WalkDir::new(&self.template_dir_path)
+            .follow_links(true)
+            .into_iter();
+        for entry_result in walker.filter_entry(|e| excludes.includes(e)) {
+            let dir_entry = entry_result?;
+            let path = dir_entry.path();
+            if !dir_entry.file_type().is_file() || path.extension() != Some(OsStr::new("hbs")) {
+                continue;
+            }
+            let text = fs::read_to_string(path).with_path(path)?;
+            if vars
+                .iter()
+                .chain(&["{{>", "@root"])
+                .any(|word| text.contains(word))
+            {
+                let rel_path = path
+                    .strip_prefix(&self.template_dir_path)
+                    .expect("strip prefix match")
+                    .with_extension("");
+                names.insert(manifest::path_key(rel_path));
+            }
+        }
+        Ok(names)
     }
 
-    pub fn gen_website(&mut self) -> anyhow::Result<usize> {
+    // manifest key and record for a single document and whether it needs to
+    // be generated (it changed since the `previous` build), or None if it
+    // isn't part of the website
+    fn website_record(
+        &self,
+        doc: &Document,
+        previous: &Manifest,
+        changes: &TemplateChanges,
+    ) -> Result<Option<(String, SourceRecord, bool)>> {
+        if self.skip_reason(doc, Format::Web).is_some() {
+            return Ok(None);
+        }
+        let outpath = self.outpath(doc);
+        let key = manifest::path_key(
+            doc.source_path
+                .strip_prefix(&self.in_path)
+                .expect("strip prefix match"),
+        );
+        let previous_record = previous.sources.get(&key);
+        let record = SourceRecord {
+            file: FileRecord::new(&doc.source_path, previous_record.map(|r| &r.file))
+                .with_path(&doc.source_path)?,
+            template: doc.template_name().map(String::from),
+            output: manifest::path_key(
+                outpath
+                    .strip_prefix(&self.out_path)
+                    .expect("strip prefix match"),
+            ),
+            inputs: self.page_inputs(doc, changes),
+            links: previous_record
+                .map(|record| record.links.clone())
+                .unwrap_or_default(),
+        };
+        let template_changed = match &record.template {
+            Some(name) => {
+                changes.rebuild_all
+                    || self
+                        .layout_chain(name)
+                        .iter()
+                        .any(|name| changes.changed.contains(*name))
+            }
+            None => false,
+        };
+        let up_to_date = match previous_record {
+            Some(previous_record) => {
+                record.file.same_content(Some(&previous_record.file))
+                    && record.template == previous_record.template
+                    && record.output == previous_record.output
+                    && record.inputs == previous_record.inputs
+            }
+            None => false,
+        };
+        // a page it links to moved, or a missing one was added
+        let links_changed = record
+            .links
+            .iter()
+            .any(|(key, url)| self.key_url(key) != *url);
+        let changed = !up_to_date || template_changed || links_changed || !outpath.exists();
+        Ok(Some((key, record, changed)))
+    }
+
+    // hashes of what a rendered page depends on besides its source file
+    // and templates, only pages that use something that changed are
+    // rendered again
+    fn page_inputs(&self, doc: &Document, changes: &TemplateChanges) -> BTreeMap<String, String> {
+        let mut inputs = BTreeMap::new();
+        // includes the values from `_defaults.yaml` files
+        if let Some(vars) = doc.front_matter() {
+            inputs.insert(
+                "front_matter".into(),
+                manifest::hash_bytes(serde_json::to_string(vars).unwrap_or_default().as_bytes()),
+            );
+        }
+        let uses = |templates: &BTreeSet<String>| {
+            doc.template_name().is_some_and(|name| {
+                self.layout_chain(name)
+                    .iter()
+                    .any(|name| templates.contains(*name))
+            })
+        };
+        if uses(&changes.page_list_templates) {
+            inputs.insert("pages".into(), changes.pages_hash.clone());
+        }
+        if uses(&changes.data_templates) {
+            inputs.insert("data".into(), self.data_hash.clone());
+        }
+        inputs
+    }
+
+    // render or copy a single document, unless it is unchanged since the
+    // `previous` build, returns its manifest key, record and whether it
+    // was generated, or None if it isn't part of the website
+    fn gen_website_document(
+        &self,
+        doc: &Document,
+        previous: &Manifest,
+        changes: &TemplateChanges,
+    ) -> Result<Option<(String, SourceRecord, bool)>> {
+        let mut result = self.website_record(doc, previous, changes)?;
+        match &mut result {
+            None => info!(
+                "skipping {}, {}",
+                doc.source_path.display(),
+                self.skip_reason(doc, Format::Web).unwrap_or_default()
+            ),
+            Some((_, _, false)) => info!("unchanged {}", doc.source_path.display()),
+            Some((_, record, true)) => {
+                let outpath = self.outpath(doc);
+                outpath.create_all_parent_dir().with_path(&outpath)?;
+                record.links = doc.webgen(self)?;
+            }
+        }
+        Ok(result)
+    }
+
+    // what `gen_website` would do with each document, nothing is written
+    pub fn website_plan(&self) -> Result<BuildPlan> {
         self.source_directory_has_files()?;
-        self.gen_website_clean_and_setup_outpath()?;
-        info!("generating html for {} files", self.doc_list.len());
-        for doc in &self.doc_list {
-            let outpath = self.outpath(doc)?;
-            outpath.create_all_parent_dir()?;
-            doc.webgen(&self)?;
+        self.check_permalinks()?;
+        let previous = Manifest::load(&self.out_path, &self.config_hash()).unwrap_or_default();
+        let changes = self.template_changes(&previous)?;
+        let mut entries = Vec::new();
+        for doc in self.doc_list.iter() {
+            let result = self
+                .website_record(doc, &previous, &changes)
+                .map_err(|e| Error::document(&doc.source_path, e))?;
+            let (action, output, reason) = match result {
+                None => (Action::Skip, None, self.skip_reason(doc, Format::Web)),
+                Some((_, _, false)) => (
+                    Action::Skip,
+                    Some(self.outpath(doc)),
+                    Some("unchanged".into()),
+                ),
+                Some((_, _, true)) if doc.is_markdown() => {
+                    (Action::Render, Some(self.outpath(doc)), None)
+                }
+                Some((_, _, true)) => (Action::Copy, Some(self.outpath(doc)), None),
+            };
+            entries.push(PlanEntry {
+                action,
+                source: doc.source_path.clone(),
+                output,
+                template: doc.template_name().map(String::from),
+                reason,
+                chapter: None,
+                spine: None,
+            });
         }
-        Ok(self.doc_list.len())
+        for dir in self.section_dirs() {
+            entries.push(PlanEntry {
+                action: Action::Render,
+                source: self.in_path.join(&dir),
+                output: Some(self.out_path.join(permalink::url_file(&section::url(&dir)))),
+                template: Some(SECTION_TEMPLATE.into()),
+                reason: Some("section page".into()),
+                chapter: None,
+                spine: None,
+            });
+        }
+        Ok(BuildPlan {
+            format: Format::Web.name().into(),
+            entries,
+        })
+    }
+
+    // what `gen_book` would do with each document, nothing is written
+    pub fn book_plan(&self) -> Result<BuildPlan> {
+        self.source_directory_has_files()?;
+        let mut entries = Vec::new();
+        let mut chapter_number = 1;
+        let mut spine_position = 1;
+        for doc in self.book_docs() {
+            let mut entry = PlanEntry {
+                action: Action::Copy,
+                source: doc.source_path.clone(),
+                output: None,
+                template: None,
+                reason: None,
+                chapter: None,
+                spine: None,
+            };
+            if let Some(reason) = self.skip_reason(doc, Format::Epub) {
+                entry.action = Action::Skip;
+                entry.reason = Some(reason);
+                entries.push(entry);
+                continue;
+            }
+            let file_name = doc.source_path.file_name().unwrap().to_string_lossy();
+            match self.book_role(doc)? {
+                BookRole::Cover => {
+                    entry.output = Some(doc.source_path.clone());
+                    entry.reason = Some("cover image".into());
+                }
+                BookRole::TitlePage => {
+                    entry.output = Some(PathBuf::from(file_name.as_ref()));
+                    entry.reason = Some("title page".into());
+                    entry.spine = Some(spine_position);
+                    spine_position += 1;
+                }
+                BookRole::Resource => {
+                    entry.output = Some(
+                        doc.source_path
+                            .strip_prefix(&self.in_path)
+                            .expect("strip prefix match")
+                            .to_path_buf(),
+                    );
+                }
+                BookRole::Chapter => {
+                    entry.output = Some(PathBuf::from(format!("{}.xhtml", doc.file_stem()?)));
+                    entry.chapter = Some(chapter_number);
+                    entry.spine = Some(spine_position);
+                    chapter_number += 1;
+                    spine_position += 1;
+                }
+            }
+            if doc.is_markdown() {
+                entry.action = Action::Render;
+                entry.template = doc.template_name().map(String::from);
+            }
+            entries.push(entry);
+        }
+        Ok(BuildPlan {
+            format: Format::Epub.name().into(),
+            entries,
+        })
+    }
+
+    // only documents (and template files) that changed since the build
+    // recorded in the output directory manifest are generated again,
+    // without a matching manifest, files from earlier builds are removed first
+    pub fn gen_website(&mut self) -> Result<usize> {
+        let report = self.build_website()?;
+        // documents that are part of the website, changed or not
+        Ok(report
+            .documents
+            .iter()
+            .filter(|doc| doc.output.is_some())
+            .count())
+    }
+
+    // like `gen_website`, returns the build report
+    pub fn build_website(&mut self) -> Result<BuildReport> {
+        self.source_directory_has_files()?;
+        self.check_out_path()?;
+        self.check_permalinks()?;
+        let mut report = BuildReport::new(Format::Web.name(), &self.out_path);
+        self.take_warnings();
+        report.phase("clean");
+        let config_hash = self.config_hash();
+        let previous = match Manifest::load(&self.out_path, &config_hash) {
+            Some(manifest) => manifest,
+            None => {
+                self.remove_outputs(false)?;
+                fs::create_dir_all(&self.out_path).with_path(&self.out_path)?;
+                Manifest::default()
+            }
+        };
+        let mut manifest = Manifest::new(config_hash);
+        self.site = self.site_vars(Format::Web);
+        let sections = self.section_dirs();
+        self.nav = Some(self.nav_tree(&sections));
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs)
+            .build()
+            .map_err(|e| Error::Config(format!("creating thread pool: {}", e)))?;
+        report.phase("assets");
+        Self::copy_files(
+            &pool,
+            &self.template_dir_path,
+            &self.out_path,
+            "hbs",
+            &self.template_excludes()?,
+            &previous.assets,
+            &mut manifest.assets,
+        )?;
+
+        report.phase("templates");
+        let changes = self.template_changes(&previous)?;
+        report.phase("documents");
+        info!(
+            "generating html for {} files, {} jobs",
+            self.doc_list.len(),
+            self.jobs
+        );
+        // results are collected in `doc_list` order, so the manifest and
+        // the reported error (the first one in that order) don't depend on
+        // which thread finishes first
+        let results: Vec<Result<Option<(String, SourceRecord, bool)>>> = pool.install(|| {
+            self.doc_list
+                .par_iter()
+                .map(|doc| {
+                    self.gen_website_document(doc, &previous, &changes)
+                        .map_err(|e| Error::document(&doc.source_path, e))
+                })
+                .collect()
+        });
+        let mut count = 0;
+        let mut updated = 0;
+        for (doc, result) in self.doc_list.iter().zip(results) {
+            let mut entry = DocumentReport {
+                source: doc.source_path.clone(),
+                output: None,
+                action: Action::Skip,
+                reason: self.skip_reason(doc, Format::Web),
+                size: None,
+            };
+            if let Some((key, record, was_updated)) = result? {
+                if was_updated {
+                    updated += 1;
+                    entry.action = if doc.is_markdown() {
+                        Action::Render
+                    } else {
+                        Action::Copy
+                    };
+                    entry.reason = None;
+                } else {
+                    entry.reason = Some("unchanged".into());
+                }
+                entry.output = Some(self.outpath(doc));
+                entry.size = file_size(self.outpath(doc));
+                manifest.sources.insert(key, record);
+                count += 1;
+            } else if self.held_back(doc).is_some() {
+                report.held_back.push(doc.source_path.clone());
+            }
+            report.add_document(entry);
+        }
+
+        report.phase("sections");
+        let entries = self.section_entries();
+        for dir in sections.iter() {
+            let was_updated = self
+                .gen_section(dir, &entries, &sections)
+                .map_err(|e| Error::document(self.in_path.join(dir), e))?;
+            let output = permalink::url_file(&section::url(dir));
+            report.add_document(DocumentReport {
+                source: self.in_path.join(dir),
+                output: Some(self.out_path.join(&output)),
+                action: if was_updated {
+                    Action::Render
+                } else {
+                    Action::Skip
+                },
+                reason: Some(
+                    if was_updated {
+                        "section page"
+                    } else {
+                        "unchanged"
+                    }
+                    .into(),
+                ),
+                size: file_size(self.out_path.join(&output)),
+            });
+            manifest
+                .sections
+                .insert(manifest::path_key(dir), manifest::path_key(output));
+            count += 1;
+            updated += usize::from(was_updated);
+        }
+
+        // remove what previous builds generated from deleted files
+        report.phase("remove");
+        let outputs = manifest.outputs();
+        for output in previous.outputs().difference(&outputs) {
+            manifest::remove_output(&self.out_path, output)
+                .with_path(self.out_path.join(output))?;
+        }
+
+        manifest.templates = changes.records;
+        manifest
+            .save(&self.out_path)
+            .with_path(self.out_path.join(manifest::MANIFEST_FILE_NAME))?;
+        self.finish_report(&mut report);
+        println!("{} of {} files updated", updated, count);
+        self.save_report(&report)?;
+        Ok(report)
     }
 }
 
+// `{{!-- layout: base --}}` or `{{! layout: base }}` on the first line of a
+// template renders it into the `body` of template `base`
+fn layout_declaration(text: &str) -> Option<&str> {
+    let line = text.lines().next()?.trim();
+    let comment = line
+        .strip_prefix("{{!--")
+        .and_then(|line| line.strip_suffix("--}}"))
+        .or_else(|| {
+            line.strip_prefix("{{!")
+                .and_then(|line| line.strip_suffix("}}"))
+        })?;
+    let layout = comment.trim().strip_prefix("layout:")?.trim();
+    (!layout.is_empty()).then_some(layout)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_layout_declaration() {
+        assert_eq!(
+            layout_declaration("{{!-- layout: base --}}\n<p>"),
+            Some("base")
+        );
+        assert_eq!(
+            layout_declaration("{{! layout: layouts/base }}"),
+            Some("layouts/base")
+        );
+        assert_eq!(layout_declaration("<p>\n{{!-- layout: base --}}"), None);
+        assert_eq!(layout_declaration("{{!-- layout: --}}"), None);
+        assert_eq!(layout_declaration("{{!-- a comment --}}"), None);
+    }
+
     #[test]
     fn test_new_web() {
         let web = Web::new("markdown", "_website", "templates").expect("new web");
@@ -380,4 +1811,108 @@ mod tests {
         assert_eq!(web.out_path, Path::new("_website"));
         assert_eq!(web.template_dir_path, Path::new("templates"));
     }
+
+    #[test]
+    fn test_check_out_path() {
+        let mut web = Web::new("markdown", "_website", "templates").expect("new web");
+        assert!(web.check_out_path().is_ok());
+        for out_path in [".", "markdown", "templates"] {
+            web.out_path = PathBuf::from(out_path);
+            assert!(web.check_out_path().is_err(), "{}", out_path);
+        }
+        web.force = true;
+        assert!(web.check_out_path().is_ok());
+    }
+
+    #[test]
+    fn test_force_build_keeps_sources() {
+        let dir = std::env::temp_dir().join(format!("webgenr-force-{}", std::process::id()));
+        let markdown = dir.join("markdown");
+        fs::create_dir_all(&markdown).unwrap();
+        fs::write(markdown.join("index.md"), "# Home\n").unwrap();
+        fs::write(dir.join("precious.txt"), "keep me").unwrap();
+        let mut web = Web::new(&markdown, &dir, &dir.join("templates")).expect("new web");
+        web.force = true;
+        web.gen_website().expect("build");
+        // without a manifest the next build starts over
+        fs::remove_file(dir.join(manifest::MANIFEST_FILE_NAME)).unwrap();
+        web.gen_website().expect("rebuild");
+        assert!(dir.join("index.html").is_file());
+        assert!(markdown.join("index.md").is_file());
+        assert!(dir.join("templates/default.hbs").is_file());
+        assert!(dir.join("precious.txt").is_file());
+        assert!(web.clean(false).is_err());
+        assert!(markdown.join("index.md").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_force_build_into_source() {
+        let dir = std::env::temp_dir().join(format!("webgenr-in-place-{}", std::process::id()));
+        fs::create_dir_all(dir.join("img")).unwrap();
+        fs::write(dir.join("index.md"), "# Home\n").unwrap();
+        fs::write(dir.join("img/logo.png"), "not really a png").unwrap();
+        let mut web = Web::new(&dir, &dir, &dir.join("templates")).expect("new web");
+        web.force = true;
+        web.gen_website().expect("build");
+        assert!(dir.join("index.html").is_file());
+        assert_eq!(
+            fs::read(dir.join("img/logo.png")).unwrap(),
+            b"not really a png"
+        );
+        assert_eq!(fs::read(dir.join("index.md")).unwrap(), b"# Home\n");
+        assert!(
+            fs::metadata(dir.join("templates/default.hbs"))
+                .unwrap()
+                .len()
+                > 0
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_relative_links_with_pretty_permalinks() {
+        let dir = std::env::temp_dir().join(format!("webgenr-links-{}", std::process::id()));
+        let markdown = dir.join("markdown");
+        fs::create_dir_all(markdown.join("notes")).unwrap();
+        fs::write(
+            markdown.join("notes/one.md"),
+            "[>](x.mp3) ![](img.png) [two](two.md#top) [up](../index.md) [top](#top)\n",
+        )
+        .unwrap();
+        fs::write(markdown.join("notes/two.md"), "# Two\n").unwrap();
+        fs::write(markdown.join("index.md"), "# Home\n").unwrap();
+        fs::write(markdown.join("notes/x.mp3"), "").unwrap();
+        fs::write(markdown.join("notes/img.png"), "").unwrap();
+        let config = Config::from_yaml("permalink: pretty").unwrap();
+        let out = dir.join("_website");
+        let mut web = Web::with_config(&markdown, &out, &dir.join("templates"), config).unwrap();
+        web.gen_website().expect("build");
+        let html = fs::read_to_string(out.join("notes/one/index.html")).unwrap();
+        assert!(html.contains("<source src=\"../x.mp3\" type=\"audio/mpeg\">"));
+        assert!(html.contains("<img src=\"../img.png\""));
+        assert!(html.contains("<a href=\"../two/#top\">two</a>"));
+        assert!(html.contains("<a href=\"../../index.html\">up</a>"));
+        assert!(html.contains("<a href=\"#top\">top</a>"));
+        assert!(out.join("notes/x.mp3").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_plan_creates_nothing() {
+        let dir = std::env::temp_dir().join(format!("webgenr-plan-{}", std::process::id()));
+        let markdown = dir.join("markdown");
+        fs::create_dir_all(&markdown).unwrap();
+        fs::write(markdown.join("index.md"), "# Home\n").unwrap();
+        let (out, templates) = (dir.join("_website"), dir.join("templates"));
+        let web = Web::read_only(&markdown, &out, &templates, Config::default()).expect("web");
+        let plan = web.website_plan().expect("plan");
+        assert_eq!(plan.count(Action::Render), 1);
+        assert!(!templates.exists());
+        assert!(!out.exists());
+        let missing = dir.join("missing");
+        assert!(Web::read_only(&missing, &out, &templates, Config::default()).is_ok());
+        assert!(!missing.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }